@@ -0,0 +1,211 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides access log parsing functionality.
+///
+/// This module defines a `parse_access_log` function that turns Apache/Nginx common (CLF) and combined access log lines
+/// into JSON objects with typed fields.
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+const COMMON_PATTERN: &str = r#"^(?P<remote_addr>\S+) (?P<ident>\S+) (?P<remote_user>\S+) \[(?P<time>[^\]]+)\] "(?P<request>(?:[^"\\]|\\.)*)" (?P<status>\d{3}) (?P<bytes>\d+|-)"#;
+const COMBINED_SUFFIX: &str =
+    r#" "(?P<referer>(?:[^"\\]|\\.)*)" "(?P<user_agent>(?:[^"\\]|\\.)*)""#;
+/// What both servers log for a field that has no value.
+const MISSING: &str = "-";
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The access log formats Apache and Nginx ship with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// `%h %l %u %t "%r" %>s %b`, the Common Log Format.
+    Common,
+    /// The common format followed by the `Referer` and `User-Agent` headers, Nginx's default.
+    Combined,
+}
+
+impl AccessLogFormat {
+    /// Returns the format a parser name such as `nginx-combined` or `apache-common` stands for.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "common" | "clf" | "nginx-common" | "apache-common" => Some(AccessLogFormat::Common),
+            "combined" | "nginx-combined" | "apache-combined" => Some(AccessLogFormat::Combined),
+            _ => None,
+        }
+    }
+
+    fn regex(&self) -> &'static Regex {
+        static COMMON: OnceLock<Regex> = OnceLock::new();
+        static COMBINED: OnceLock<Regex> = OnceLock::new();
+        match self {
+            AccessLogFormat::Common => {
+                COMMON.get_or_init(|| Regex::new(COMMON_PATTERN).expect("pattern is valid"))
+            }
+            AccessLogFormat::Combined => COMBINED.get_or_init(|| {
+                Regex::new(&format!("{COMMON_PATTERN}{COMBINED_SUFFIX}")).expect("pattern is valid")
+            }),
+        }
+    }
+}
+
+/// Parses a single access log line into a JSON object.
+///
+/// The request line is split into `method`, `path`, `query` and `protocol`, `status` and `bytes` are numbers,
+/// and `time` is converted to RFC 3339 (`2000-10-10T13:55:36-07:00`). Fields logged as `-` are left out.
+///
+/// Returns `None` if the line is not in `format`. Servers that append more fields to the format still match, the extra fields are ignored.
+pub fn parse_access_log(line: &str, format: AccessLogFormat) -> Option<Value> {
+    let captures = format.regex().captures(line)?;
+    let mut record = Map::new();
+    let mut insert_text = |name: &str, text: &str| {
+        if text != MISSING {
+            record.insert(name.to_string(), Value::String(unescape(text)));
+        }
+    };
+
+    insert_text("remote_addr", &captures["remote_addr"]);
+    insert_text("remote_user", &captures["remote_user"]);
+    if let Some(referer) = captures.name("referer") {
+        insert_text("referer", referer.as_str());
+    }
+    if let Some(user_agent) = captures.name("user_agent") {
+        insert_text("user_agent", user_agent.as_str());
+    }
+
+    let time = &captures["time"];
+    record.insert(
+        "time".to_string(),
+        Value::String(to_rfc3339(time).unwrap_or_else(|| time.to_string())),
+    );
+
+    let request = unescape(&captures["request"]);
+    let parts: Vec<&str> = request.split(' ').collect();
+    match parts.as_slice() {
+        [method, target, protocol] => {
+            let (path, query) = match target.split_once('?') {
+                Some((path, query)) => (path, Some(query)),
+                None => (*target, None),
+            };
+            record.insert("method".to_string(), Value::from(*method));
+            record.insert("path".to_string(), Value::from(path));
+            if let Some(query) = query {
+                record.insert("query".to_string(), Value::from(query));
+            }
+            record.insert("protocol".to_string(), Value::from(*protocol));
+        }
+        // malformed requests (e.g. a TLS handshake sent to a plain port) are kept as they were logged
+        _ if request != MISSING => {
+            record.insert("request".to_string(), Value::String(request.clone()));
+        }
+        _ => {}
+    }
+
+    record.insert(
+        "status".to_string(),
+        Value::from(captures["status"].parse::<u64>().ok()?),
+    );
+    if let Ok(bytes) = captures["bytes"].parse::<u64>() {
+        record.insert("bytes".to_string(), Value::from(bytes));
+    }
+
+    Some(Value::Object(record))
+}
+
+/// Converts `10/Oct/2000:13:55:36 -0700` to `2000-10-10T13:55:36-07:00`.
+fn to_rfc3339(time: &str) -> Option<String> {
+    let (date_time, offset) = time.split_once(' ')?;
+    let mut date_parts = date_time.splitn(3, '/');
+    let day = date_parts.next()?;
+    let month = date_parts.next()?;
+    let (year, clock) = date_parts.next()?.split_once(':')?;
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+
+    let valid = day.len() == 2
+        && year.len() == 4
+        && clock.len() == 8
+        && offset.len() == 5
+        && offset.starts_with(['+', '-']);
+    if !valid {
+        return None;
+    }
+    Some(format!(
+        "{year}-{month:02}-{day}T{clock}{}:{}",
+        &offset[..3],
+        &offset[3..]
+    ))
+}
+
+/// Resolves the escapes both servers use inside quoted fields: Apache's `\"` and `\\`, and Nginx's `\xNN`.
+fn unescape(text: &str) -> String {
+    if !text.contains('\\') {
+        return text.to_string();
+    }
+
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match rest {
+            [b'x', hi, lo, tail @ ..] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+                let hex = [*hi, *lo];
+                let hex = std::str::from_utf8(&hex).unwrap_or("00");
+                bytes.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+                rest = tail;
+            }
+            [escaped @ (b'"' | b'\\'), tail @ ..] => {
+                bytes.push(*escaped);
+                rest = tail;
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_combined() {
+        let line = r#"203.0.113.9 - alice [10/Oct/2000:13:55:36 -0700] "GET /search?q=a%20b HTTP/1.1" 200 2326 "https://example.com/" "curl/8.0 \x22quoted\x22""#;
+        assert_eq!(
+            parse_access_log(line, AccessLogFormat::Combined),
+            Some(json!({
+                "remote_addr": "203.0.113.9",
+                "remote_user": "alice",
+                "time": "2000-10-10T13:55:36-07:00",
+                "method": "GET",
+                "path": "/search",
+                "query": "q=a%20b",
+                "protocol": "HTTP/1.1",
+                "status": 200,
+                "bytes": 2326,
+                "referer": "https://example.com/",
+                "user_agent": "curl/8.0 \"quoted\"",
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_common_with_missing_fields() {
+        let line = r#"::1 - - [01/Feb/2024:00:00:01 +0000] "-" 400 -"#;
+        assert_eq!(
+            parse_access_log(line, AccessLogFormat::Common),
+            Some(json!({
+                "remote_addr": "::1",
+                "time": "2024-02-01T00:00:01+00:00",
+                "status": 400,
+            }))
+        );
+        assert_eq!(parse_access_log(line, AccessLogFormat::Combined), None);
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides reading of compressed and rotated log archives.
+///
+/// This module defines an `ArchiveReader` that reads a file once from start to end, transparently decompressing
+/// gzip and zstd input, and a `rotation_set` function that finds the rotated archives of a live log file.
+use std::fs::{self, File, Metadata};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use flate2::read::MultiGzDecoder;
+use rusqlite::Connection;
+
+use crate::concurrency_helper::SharedState;
+use crate::storage::{load_file_offset, save_file_offset, FileOffset};
+use crate::tailing::file_id;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const COMPRESSED_EXTENSIONS: [&str; 3] = [".gz", ".zst", ".zstd"];
+/// How many decompressed bytes from the start of an archive identify it, along with its decompressed size.
+const FINGERPRINT_LEN: u64 = 4096;
+/// Prefix of the keys archive offsets are persisted under, telling them apart from the paths of followed files.
+const ARCHIVE_KEY_PREFIX: &str = "archive:";
+
+/// The compression format of a file, detected from its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Detects the compression format from the first bytes of a file.
+pub fn detect_compression(header: &[u8]) -> Compression {
+    if header.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if header.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+/// Returns whether the file at `path` is gzip or zstd compressed.
+pub fn is_compressed(path: &Path) -> bool {
+    let mut header = [0u8; 4];
+    let read = File::open(path).and_then(|mut file| file.read(&mut header));
+    match read {
+        Ok(n) => detect_compression(&header[..n]) != Compression::None,
+        Err(_) => false,
+    }
+}
+
+/// Opens a file and wraps it in the decoder matching its magic bytes.
+fn open_decoded(file: File) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(file);
+    let compression = detect_compression(reader.fill_buf()?);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    })
+}
+
+/// Reads a finished log file, compressed or not, line by line from start to end.
+///
+/// Unlike `FileTailer`, the iterator ends at the end of the file. The number of decompressed bytes consumed is persisted
+/// in the DB, so a later run against the same DB skips what was already ingested, including whole archives.
+/// The offset is keyed by the decompressed content of the archive rather than its path, so it is still found after
+/// logrotate renamed the archive (`app.log.1` to `app.log.2`) or compressed it (`app.log.2.gz`).
+/// An archive that has no offset of its own yet but is still the file a `FileTailer` followed under its live name
+/// (renamed, not copied or compressed) continues from the tailer's offset instead of from the start.
+pub struct ArchiveReader {
+    /// The path of the archive.
+    path: PathBuf,
+    /// The live file the archive was rotated from, if any.
+    rotated_from: Option<PathBuf>,
+    /// The decoded content, `None` until the archive is opened and after it is exhausted.
+    reader: Option<Box<dyn BufRead + Send>>,
+    /// Whether the archive has been opened, it is opened lazily so chained archives are read one at a time.
+    opened: bool,
+    /// The key of the persisted offset, a fingerprint of the content known once the archive is opened.
+    key: String,
+    /// Decompressed bytes consumed so far.
+    offset: u64,
+    /// The offset persisted by a previous run, if any.
+    persisted: Option<FileOffset>,
+    shared_connection: Arc<Mutex<Connection>>,
+    signal: Arc<SharedState>,
+}
+
+impl ArchiveReader {
+    /// Creates a reader for the archive at `path` rotated from the live file at `rotated_from`, it is not opened until the first line is requested.
+    pub fn new(
+        path: PathBuf,
+        rotated_from: Option<PathBuf>,
+        shared_connection: Arc<Mutex<Connection>>,
+        signal: Arc<SharedState>,
+    ) -> ArchiveReader {
+        ArchiveReader {
+            path,
+            rotated_from,
+            reader: None,
+            opened: false,
+            key: String::new(),
+            offset: 0,
+            persisted: None,
+            shared_connection,
+            signal,
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let file = File::open(&self.path)?;
+        let meta = file.metadata()?;
+        let mut reader = open_decoded(file)?;
+        let mut head = Vec::new();
+        (&mut reader).take(FINGERPRINT_LEN).read_to_end(&mut head)?;
+        self.key = fingerprint(&head, decoded_len(&self.path, &meta)?);
+        self.persisted = load_file_offset(Arc::clone(&self.shared_connection), &self.key)
+            .map_err(|e| io::Error::other(format!("failed to load persisted offset: {e}")))?;
+        let mut reader: Box<dyn BufRead + Send> = Box::new(io::Cursor::new(head).chain(reader));
+
+        // decompressed streams cannot seek, so skip over what a previous run already ingested
+        let resume_at = match self.persisted {
+            Some(saved) => Some(saved.offset),
+            None => self.tailed_offset(&meta)?,
+        };
+        if let Some(resume_at) = resume_at {
+            self.offset = io::copy(&mut (&mut reader).take(resume_at), &mut io::sink())?;
+        }
+
+        self.reader = Some(reader);
+        Ok(())
+    }
+
+    /// Returns the offset a `FileTailer` persisted for the live file, if the archive is still that file under another name.
+    ///
+    /// A copied or compressed archive has another identity, so it never picks up the offset of the live file it replaced.
+    fn tailed_offset(&self, meta: &Metadata) -> io::Result<Option<u64>> {
+        let live = match &self.rotated_from {
+            Some(live) if !is_compressed(&self.path) => {
+                fs::canonicalize(live).unwrap_or_else(|_| live.clone())
+            }
+            _ => return Ok(None),
+        };
+        let saved = load_file_offset(Arc::clone(&self.shared_connection), &live.to_string_lossy())
+            .map_err(|e| io::Error::other(format!("failed to load persisted offset: {e}")))?;
+
+        Ok(saved
+            .filter(|saved| {
+                saved.file_id.is_some()
+                    && saved.file_id == file_id(meta)
+                    && saved.offset <= meta.len()
+            })
+            .map(|saved| saved.offset))
+    }
+
+    fn persist(&mut self) {
+        let current = FileOffset {
+            file_id: None,
+            offset: self.offset,
+        };
+        if self.persisted == Some(current) {
+            return;
+        }
+
+        match save_file_offset(Arc::clone(&self.shared_connection), &self.key, current) {
+            Ok(()) => self.persisted = Some(current),
+            Err(e) => eprintln!(
+                "Failed to persist offset for {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Stops reading and records how far reading got.
+    fn finish(&mut self) -> Option<String> {
+        if self.reader.take().is_some() {
+            self.persist();
+        }
+        None
+    }
+}
+
+impl Iterator for ArchiveReader {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if !self.opened {
+            self.opened = true;
+            if let Err(e) = self.open() {
+                eprintln!("Failed to open archive {}: {}", self.path.display(), e);
+                return None;
+            }
+            println!("Reading archive {}", self.path.display());
+        }
+
+        if self.signal.is_stopped() {
+            return self.finish();
+        }
+
+        let reader = self.reader.as_mut()?;
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => self.finish(),
+            Ok(n) => {
+                self.offset += n as u64;
+                let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                Some(String::from_utf8_lossy(line).to_string())
+            }
+            Err(e) => {
+                eprintln!("Error reading archive {}: {}", self.path.display(), e);
+                self.finish()
+            }
+        }
+    }
+}
+
+/// Returns the key an archive starting with `head` and `len` bytes long, once decompressed, persists its offset under:
+/// the FNV-1a hash of `head` and `len`.
+///
+/// Only archives of the same size whose first `FINGERPRINT_LEN` decompressed bytes are identical share a key.
+fn fingerprint(head: &[u8], len: u64) -> String {
+    let hash = head
+        .iter()
+        .chain(&len.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{ARCHIVE_KEY_PREFIX}{len}:{hash:016x}")
+}
+
+/// Returns the decompressed size of the file at `path`, a compressed file is decoded once to count it.
+fn decoded_len(path: &Path, meta: &Metadata) -> io::Result<u64> {
+    if is_compressed(path) {
+        io::copy(&mut open_decoded(File::open(path)?)?, &mut io::sink())
+    } else {
+        Ok(meta.len())
+    }
+}
+
+/// Finds the rotated archives of the live log file at `path`, oldest first.
+///
+/// Archives are siblings whose name is the live file's name followed by `.`, `-` or `_` and a suffix, e.g. `app.log.1`,
+/// `app.log.2.gz` or `app.log-20240101.zst`. If every suffix is a number (logrotate's default) a higher number is older,
+/// otherwise archives are ordered by modification time.
+pub fn rotation_set(path: &Path) -> Vec<PathBuf> {
+    let file_name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => return Vec::new(),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut archives: Vec<(PathBuf, Option<u64>, SystemTime)> = Vec::new();
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let archive_path = path.with_file_name(entry.file_name());
+            let name = match archive_path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let suffix = match archive_suffix(name, &file_name) {
+                Some(suffix) => suffix,
+                None => continue,
+            };
+            if !archive_path.is_file() {
+                continue;
+            }
+
+            let suffix = COMPRESSED_EXTENSIONS
+                .iter()
+                .find_map(|ext| suffix.strip_suffix(ext))
+                .unwrap_or(suffix);
+            let index = suffix.parse::<u64>().ok();
+            let modified = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            archives.push((archive_path, index, modified));
+        }
+    }
+
+    if archives.iter().all(|(_, index, _)| index.is_some()) {
+        archives.sort_by_key(|archive| std::cmp::Reverse(archive.1));
+    } else {
+        archives.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
+    }
+
+    archives.into_iter().map(|(path, _, _)| path).collect()
+}
+
+/// Returns whether `path` is named like a rotated archive of the live log file at `live`, see `rotation_set`.
+pub fn is_archive_of(path: &Path, live: &Path) -> bool {
+    if path.parent() != live.parent() {
+        return false;
+    }
+    match (
+        path.file_name().and_then(|name| name.to_str()),
+        live.file_name().and_then(|name| name.to_str()),
+    ) {
+        (Some(name), Some(live_name)) => archive_suffix(name, live_name).is_some(),
+        _ => false,
+    }
+}
+
+/// The suffix an archive named `name` adds to the live file's name without its separator, e.g. `2.gz` for `app.log.2.gz`.
+fn archive_suffix<'a>(name: &'a str, live_name: &str) -> Option<&'a str> {
+    match name.strip_prefix(live_name) {
+        Some(suffix) if suffix.len() > 1 && suffix.starts_with(['.', '-', '_']) => {
+            Some(&suffix[1..])
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(detect_compression(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(
+            detect_compression(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(detect_compression(b"{\"a\":1}"), Compression::None);
+        assert_eq!(detect_compression(&[]), Compression::None);
+    }
+
+    #[test]
+    fn test_rotation_set_orders_numbered_archives_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let live = dir.join("app.log");
+        fs::write(&live, "live\n").unwrap();
+        fs::write(dir.join("app.log.1"), "one\n").unwrap();
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("app.log.2.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        gz.write_all(b"two\n").unwrap();
+        gz.finish().unwrap();
+        fs::write(dir.join("other.log.3"), "unrelated\n").unwrap();
+
+        let names: Vec<String> = rotation_set(&live)
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["app.log.2.gz", "app.log.1"]);
+        assert!(is_compressed(&dir.join("app.log.2.gz")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_offsets_follow_archives_across_renames_and_compression() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let read = |path: &Path| -> Vec<String> {
+            ArchiveReader::new(
+                path.to_path_buf(),
+                None,
+                Arc::clone(&conn),
+                Arc::new(SharedState::new()),
+            )
+            .collect()
+        };
+
+        fs::write(dir.join("app.log.1"), "one\ntwo\n").unwrap();
+        fs::write(dir.join("other.log.1"), "three\n").unwrap();
+        assert_eq!(read(&dir.join("app.log.1")), vec!["one", "two"]);
+
+        // logrotate compresses the archive under the next number
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("app.log.2.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        gz.write_all(&fs::read(dir.join("app.log.1")).unwrap())
+            .unwrap();
+        gz.finish().unwrap();
+        fs::remove_file(dir.join("app.log.1")).unwrap();
+        assert!(read(&dir.join("app.log.2.gz")).is_empty());
+        assert_eq!(read(&dir.join("other.log.1")), vec!["three"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archives_sharing_their_start_keep_their_own_offsets() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let read = |path: &Path| -> usize {
+            ArchiveReader::new(
+                path.to_path_buf(),
+                None,
+                Arc::clone(&conn),
+                Arc::new(SharedState::new()),
+            )
+            .count()
+        };
+
+        let head = format!("{}\n", "x".repeat(FINGERPRINT_LEN as usize));
+        fs::write(dir.join("app.log.1"), format!("{head}one\n")).unwrap();
+        fs::write(dir.join("app.log.2"), format!("{head}one\ntwo\n")).unwrap();
+        assert_eq!(read(&dir.join("app.log.1")), 2);
+        assert_eq!(read(&dir.join("app.log.2")), 3);
+        assert_eq!(read(&dir.join("app.log.1")), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_renamed_live_file_continues_from_the_tailed_offset() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let live = dir.join("app.log");
+        let read = |path: &Path| -> Vec<String> {
+            ArchiveReader::new(
+                path.to_path_buf(),
+                Some(live.clone()),
+                Arc::clone(&conn),
+                Arc::new(SharedState::new()),
+            )
+            .collect()
+        };
+
+        // a previous run tailed the first line of the live file
+        fs::write(&live, "one\ntwo\n").unwrap();
+        let tailed = FileOffset {
+            file_id: file_id(&fs::metadata(&live).unwrap()),
+            offset: 4,
+        };
+        save_file_offset(
+            Arc::clone(&conn),
+            &fs::canonicalize(&live).unwrap().to_string_lossy(),
+            tailed,
+        )
+        .unwrap();
+
+        // logrotate renames it and creates a new live file
+        fs::rename(&live, dir.join("app.log.1")).unwrap();
+        fs::write(&live, "three\n").unwrap();
+        assert_eq!(read(&dir.join("app.log.1")), vec!["two"]);
+
+        // once read, the archive is found by its own offset after further renames
+        fs::rename(dir.join("app.log.1"), dir.join("app.log.2")).unwrap();
+        assert!(read(&dir.join("app.log.2")).is_empty());
+
+        // the file that replaced it has another identity, so it is read from the start
+        fs::write(&live, "one\nthree\n").unwrap();
+        fs::rename(&live, dir.join("app.log.3")).unwrap();
+        assert_eq!(read(&dir.join("app.log.3")), vec!["one", "three"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
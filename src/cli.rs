@@ -3,19 +3,505 @@
 /// Provides command line interface (CLI) argument parsing functionality.
 ///
 /// This module uses the `clap` crate to define and parse CLI arguments.
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use clap::Parser;
 use rusqlite::Connection;
 
-use crate::{concurrency_helper::SharedState, ingestion::add_src};
+use crate::{
+    concurrency_helper::SharedState,
+    docker::{ContainerFilter, DEFAULT_DOCKER_SOCKET},
+    grok::GrokLibrary,
+    ingestion::{
+        add_docker_src, add_file_src, add_glob_src, add_http_src, add_src, add_stdin_src,
+        add_syslog_src, sanitize_table_name, ArrayModes, ArrayRule, IngestOptions, LineFormat,
+        SourceParser, StderrMode,
+    },
+    multiline::Multiline,
+    prefix::PrefixRule,
+    storage::{CaseCollision, Overflow, TableOptions, TypeConflict, DEFAULT_MAX_COLUMNS},
+    timestamp::TimestampRule,
+    unified_view::ViewCatalog,
+};
+
+/// The source string that stands for standard input.
+const STDIN_SRC: &str = "-";
+/// The name `--source-parser` refers to the docker source by.
+const DOCKER_SRC: &str = "docker";
+/// The name the flattening `--source-*` overrides refer to the syslog source by, its frames are always parsed as syslog.
+const SYSLOG_SRC: &str = "syslog";
 
 /// Represents the arguments for the noninteractive mode.
 #[derive(Parser, Debug, Clone)]
 pub struct NoninteractiveArgs {
-    /// A vector of strings representing the data sources to ingest.
+    /// A vector of strings representing the data sources to ingest, `-` reads from standard input.
+    /// A source can be named `<name>=<command>` (e.g. `web="docker logs web -f"`), its table is then named after it instead of the command.
+    /// Names are letters, digits and underscores starting with a letter. Text that reads as an environment assignment, an all-uppercase
+    /// name (`LEVEL=debug ./app`) or a command starting with another assignment (`web=LEVEL=debug ./app`), is taken as a command.
+    /// `<name>:=<command>` always names the source, `:=` cannot start a shell command.
     #[clap(short, long)]
     pub srcs: Vec<String>,
+    /// How the lines of command, file, glob, stdin and docker sources are parsed: `auto` (JSON, else logfmt), `json`, `logfmt`, `syslog`,
+    /// `nginx-combined`/`apache-combined`, `nginx-common`/`apache-common`,
+    /// `regex:<regex with named captures>` or `grok:<expression>` (e.g. `grok:%{IP:client} %{WORD:method}`).
+    /// Lines that do not parse still land in the `raw_unparsable_line` column.
+    #[clap(long, default_value = "auto")]
+    pub parser: String,
+    /// Overrides `--parser` for a single source, as `<source>=<parser>` where `<source>` is the source as given (or its table name).
+    #[clap(long)]
+    pub source_parser: Vec<String>,
+    /// Files of additional grok patterns, one `NAME regex` definition per line, that grok parsers can reference.
+    #[clap(long)]
+    pub grok_patterns: Vec<String>,
+    /// Strips a prefix from every line before parsing and keeps it in `_prefix_*` columns: `docker` (`docker logs -t` timestamps),
+    /// `kubectl` (`kubectl logs --prefix`), `compose` (`docker compose logs`), `auto` to detect any of them, or `regex:<regex with named captures>`.
+    #[clap(long)]
+    pub prefix: Option<String>,
+    /// Overrides `--prefix` for a single source, as `<source>=<prefix>`.
+    #[clap(long)]
+    pub source_prefix: Vec<String>,
+    /// Joins several lines into one record before parsing: `continuation:<regex>` (matching lines belong to the previous record,
+    /// e.g. `continuation:^\s+at ` for Java stack traces), `start:<regex>` (matching lines start a new record) or `json` (pretty-printed JSON).
+    #[clap(long)]
+    pub multiline: Option<String>,
+    /// Overrides `--multiline` for a single source, as `<source>=<rule>`.
+    #[clap(long)]
+    pub source_multiline: Vec<String>,
+    /// How long a multi-line record waits for more lines before it is written anyway.
+    #[clap(long, default_value = "1000")]
+    pub multiline_flush_ms: u64,
+    /// Decodes JSON documents embedded in string values (e.g. `"message": "{\"user\":42}"` becomes the `message.user` column),
+    /// up to this many levels deep. 0 keeps them as strings.
+    #[clap(long, default_value = "0")]
+    pub embedded_json_depth: usize,
+    /// How arrays are stored: `index` (`tags[0]`, `tags[1]`, ... columns), `json` (a single JSON text column, see SQLite's `json_each`)
+    /// or `rows` (one row per element in a child table `<table>__<key path>`, linked to the `_row_id` of the record by `_parent_id`).
+    /// Give it as `<key path>=<mode>` (e.g. `request.errors=rows`) to pick the mode of a single key path, repeat for several.
+    #[clap(long)]
+    pub arrays: Vec<String>,
+    /// Overrides `--arrays` for a single source, as `<source>=<mode>` or `<source>=<key path>=<mode>`.
+    #[clap(long)]
+    pub source_arrays: Vec<String>,
+    /// Keeps only the values whose key path (the column name without array indices) matches one of these globs, e.g. `http.*`.
+    /// A matching object keeps everything below it. Every value is kept if none is given.
+    #[clap(long)]
+    pub include: Vec<String>,
+    /// Drops the values whose key path matches one of these globs along with everything below them, e.g. `http.request.body`.
+    #[clap(long)]
+    pub exclude: Vec<String>,
+    /// Adds to `--include` for a single source, as `<source>=<glob>`.
+    #[clap(long)]
+    pub source_include: Vec<String>,
+    /// Adds to `--exclude` for a single source, as `<source>=<glob>`.
+    #[clap(long)]
+    pub source_exclude: Vec<String>,
+    /// Flattens keys only this many levels deep, at least 1 (2 flattens `http.status` but stores `http.request` as JSON text). Every level is flattened if not given.
+    #[clap(long)]
+    pub max_depth: Option<usize>,
+    /// Overrides `--max-depth` for a single source, as `<source>=<depth>`.
+    #[clap(long)]
+    pub source_max_depth: Vec<String>,
+    /// Where the event timestamp stored in the indexed `_ts` column comes from: `auto` looks for the common field names
+    /// (`@timestamp`, `timestamp`, `ts`, `time`, ...) and formats (RFC 3339, epoch seconds/millis/micros/nanos), or
+    /// `<field>[,<field>...][:<format>]` with format `rfc3339`, `epoch_s`, `epoch_ms`, `epoch_us`, `epoch_ns` or a strftime layout.
+    /// Rows without a timestamp get their ingestion time.
+    #[clap(long, default_value = "auto")]
+    pub timestamp: String,
+    /// Overrides `--timestamp` for a single source, as `<source>=<spec>`.
+    #[clap(long)]
+    pub source_timestamp: Vec<String>,
+    /// What happens when a field's type changes across lines: `keep` storing the value in its existing column,
+    /// or `split` storing it in a sibling column named after its type (e.g. `latency__text`).
+    #[clap(long, default_value = "keep")]
+    pub type_conflict: TypeConflict,
+    /// What happens when keys differ only in case (`Level` and `level`), which SQLite column names do not tell apart:
+    /// `merge` them into the column seen first, or give the later key its own `suffix`ed column (e.g. `Level__2`).
+    /// Either way the mapping is recorded in the `_column_mappings` table.
+    #[clap(long, default_value = "merge")]
+    pub case_collision: CaseCollision,
+    /// How many columns a table may grow to, SQLite refuses more than 2000. Keys seen after that are demoted to overflow storage.
+    #[clap(long, default_value_t = DEFAULT_MAX_COLUMNS)]
+    pub max_columns: usize,
+    /// Where keys demoted past `--max-columns` go: a `json` object in the `_overflow` column,
+    /// or a `<table>___overflow` child `table` with one `key`/`value` row per key. The `_demoted_keys` table lists them.
+    #[clap(long, default_value = "json")]
+    pub overflow: Overflow,
+    /// Where the standard error of command sources goes: `merge` into the same table (with a `_stream` column),
+    /// a sibling `<table>_stderr` `table`, or `inherit` to print it to the terminal.
+    #[clap(long, default_value = "merge")]
+    pub stderr: StderrMode,
+    /// Ingest standard input, same as `-s -`. The session ends when the input ends or on Ctrl+C.
+    #[clap(long)]
+    pub stdin: bool,
+    /// A vector of file paths to follow natively, surviving log rotation.
+    #[clap(short, long = "file")]
+    pub files: Vec<String>,
+    /// Also ingest the rotated archives of every file source (e.g. `app.log.1`, `app.log.2.gz`), oldest first, before following it.
+    #[clap(long)]
+    pub include_rotated: bool,
+    /// A vector of glob patterns (or directories) whose matching files are ingested as they appear, one table per pattern.
+    #[clap(short, long = "glob")]
+    pub globs: Vec<String>,
+    /// An optional address to receive syslog (RFC 3164/5424) datagrams on, e.g. `127.0.0.1:5514`.
+    #[clap(long)]
+    pub syslog_udp: Option<String>,
+    /// An optional address to accept syslog connections on, octet-counted and newline delimited framing are supported.
+    #[clap(long)]
+    pub syslog_tcp: Option<String>,
+    /// An optional address to accept `POST /ingest/<source>` requests with NDJSON or JSON array bodies on, e.g. `127.0.0.1:9880`.
+    /// OTLP/HTTP log exports (JSON or protobuf) are accepted on `POST /v1/logs` of the same address.
+    /// HTTP sources are only known once they post, so they take the global options and `--source-*` overrides cannot name them.
+    #[clap(long)]
+    pub http: Option<String>,
+    /// Follow the logs of running containers through the Docker Engine API, containers started later are picked up too.
+    #[clap(long)]
+    pub docker: bool,
+    /// Restricts `--docker` to matching containers, `name=<name>` or `label=<key>[=<value>]`, like `docker ps --filter`.
+    #[clap(long)]
+    pub docker_filter: Vec<String>,
+    /// The Docker Engine socket `--docker` talks to.
+    #[clap(long, default_value = DEFAULT_DOCKER_SOCKET)]
+    pub docker_socket: String,
+}
+
+impl NoninteractiveArgs {
+    /// Returns whether standard input is one of the sources, in which case it cannot be used to read the 'q' keypress.
+    pub fn reads_stdin(&self) -> bool {
+        self.stdin || self.srcs.iter().any(|src| src == STDIN_SRC)
+    }
+
+    /// Splits every command source into its name and command, rejecting invalid names and names given to more than one command.
+    fn command_sources(&self) -> Result<Vec<CommandSource>, String> {
+        let mut sources: Vec<CommandSource> = Vec::new();
+        for src in self.srcs.iter().filter(|src| *src != STDIN_SRC) {
+            let source = CommandSource::parse(src)?;
+            if let Some(alias) = &source.alias {
+                let taken = sources.iter().any(|other| {
+                    other
+                        .alias
+                        .as_ref()
+                        .is_some_and(|other| other.eq_ignore_ascii_case(alias))
+                });
+                if taken {
+                    return Err(format!(
+                        "source name {alias} is given to more than one source"
+                    ));
+                }
+            }
+            sources.push(source);
+        }
+        Ok(sources)
+    }
+
+    /// Returns every name `--source-*` overrides can refer to a source by: each source as given and as its table name.
+    fn source_names(&self) -> Result<Vec<String>, String> {
+        let mut names: Vec<String> = self
+            .command_sources()?
+            .iter()
+            .map(|source| source.name().to_string())
+            .chain(self.files.iter().cloned())
+            .chain(self.globs.iter().cloned())
+            .collect();
+        if self.reads_stdin() {
+            names.push(STDIN_SRC.to_string());
+        }
+        if self.docker {
+            names.push(DOCKER_SRC.to_string());
+        }
+        let table_names: Vec<String> = names.iter().map(|name| sanitize_table_name(name)).collect();
+        names.extend(table_names);
+        Ok(names)
+    }
+
+    /// Returns every name the flattening `--source-*` overrides can refer to a source by, the syslog source's too unlike `source_names`.
+    fn option_source_names(&self) -> Result<Vec<String>, String> {
+        let mut names = self.source_names()?;
+        if self.syslog_udp.is_some() || self.syslog_tcp.is_some() {
+            names.push(SYSLOG_SRC.to_string());
+        }
+        Ok(names)
+    }
+
+    /// Builds the flattening options of every source from `--embedded-json-depth`, `--arrays`, `--include`, `--exclude`, `--max-depth`, `--timestamp`, `--type-conflict`, `--case-collision`, `--max-columns`, `--overflow` and their `--source-*` overrides.
+    fn source_options(&self) -> Result<SourceOptions, String> {
+        let sources = self.option_source_names()?;
+        let mut global = IngestOptions {
+            embedded_json_depth: self.embedded_json_depth,
+            arrays: ArrayModes::default(),
+            include: self
+                .include
+                .iter()
+                .map(|spec| key_path_glob(spec))
+                .collect::<Result<_, _>>()?,
+            exclude: self
+                .exclude
+                .iter()
+                .map(|spec| key_path_glob(spec))
+                .collect::<Result<_, _>>()?,
+            max_depth: self.max_depth.map(checked_max_depth).transpose()?,
+            timestamp: TimestampRule::from_spec(&self.timestamp)?,
+            table: TableOptions {
+                type_conflict: self.type_conflict,
+                case_collision: self.case_collision,
+                max_columns: self.max_columns,
+                overflow: self.overflow,
+            },
+        };
+        for spec in &self.arrays {
+            global.arrays.apply(&spec.parse()?);
+        }
+
+        Ok(SourceOptions {
+            global,
+            source_arrays: parse_overrides(&self.source_arrays, &sources, ArrayRule::from_str)?,
+            source_includes: parse_overrides(&self.source_include, &sources, key_path_glob)?,
+            source_excludes: parse_overrides(&self.source_exclude, &sources, key_path_glob)?,
+            source_timestamps: parse_overrides(
+                &self.source_timestamp,
+                &sources,
+                TimestampRule::from_spec,
+            )?,
+            source_max_depths: parse_overrides(&self.source_max_depth, &sources, |spec| {
+                spec.parse::<usize>()
+                    .map_err(|e| format!("invalid depth {spec}: {e}"))
+                    .and_then(checked_max_depth)
+            })?,
+        })
+    }
+
+    /// Builds the parser of every source from `--parser`, `--prefix`, `--multiline`, their `--source-*` overrides and `--grok-patterns`.
+    fn source_parsers(&self) -> Result<SourceParsers, String> {
+        let sources = self.source_names()?;
+        let mut grok_library = GrokLibrary::default();
+        for path in &self.grok_patterns {
+            grok_library.load_file(Path::new(path))?;
+        }
+        let line_format = |spec: &str| LineFormat::from_spec(spec, &grok_library);
+        let flush_timeout = Duration::from_millis(self.multiline_flush_ms);
+        let multiline = |spec: &str| Multiline::from_spec(spec, flush_timeout);
+
+        Ok(SourceParsers {
+            line_format: line_format(&self.parser)?,
+            source_line_formats: parse_overrides(&self.source_parser, &sources, line_format)?,
+            prefix: self
+                .prefix
+                .as_deref()
+                .map(PrefixRule::from_spec)
+                .transpose()?,
+            source_prefixes: parse_overrides(&self.source_prefix, &sources, PrefixRule::from_spec)?,
+            multiline: self.multiline.as_deref().map(multiline).transpose()?,
+            source_multilines: parse_overrides(&self.source_multiline, &sources, multiline)?,
+        })
+    }
+}
+
+/// Parses a `--include`/`--exclude` glob.
+fn key_path_glob(spec: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(spec).map_err(|e| format!("invalid key path glob {spec}: {e}"))
+}
+
+/// Rejects a `--max-depth` of 0, which would store the whole record as JSON text in a column without a name.
+fn checked_max_depth(max_depth: usize) -> Result<usize, String> {
+    if max_depth == 0 {
+        return Err(
+            "invalid depth 0, --max-depth flattens at least the top level of a record".to_string(),
+        );
+    }
+    Ok(max_depth)
+}
+
+/// Parses `<source>=<spec>` overrides of a per-source option, rejecting those that name none of `sources`.
+///
+/// Both commands and specs may contain `=` (`LEVEL=debug ./app`, `request.errors=rows`), so the source is the text before
+/// the last `=` that leaves one of `sources` in front of it.
+fn parse_overrides<T>(
+    overrides: &[String],
+    sources: &[String],
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<(String, T)>, String> {
+    overrides
+        .iter()
+        .map(|override_spec| {
+            let (src, spec) = override_spec
+                .rmatch_indices('=')
+                .map(|(i, _)| (&override_spec[..i], &override_spec[i + 1..]))
+                .find(|(src, _)| sources.iter().any(|source| source == src))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid source override {override_spec}, expected <source>=<value> naming one of the sources"
+                    )
+                })?;
+            Ok((src.to_string(), parse(spec)?))
+        })
+        .collect()
+}
+
+/// The parser of every source, the global options unless a `--source-*` override names the source.
+struct SourceParsers {
+    line_format: LineFormat,
+    source_line_formats: Vec<(String, LineFormat)>,
+    prefix: Option<PrefixRule>,
+    source_prefixes: Vec<(String, PrefixRule)>,
+    multiline: Option<Multiline>,
+    source_multilines: Vec<(String, Multiline)>,
+}
+
+impl SourceParsers {
+    /// Returns the parser for `src`, which is matched both as given on the command line and as its table name.
+    fn for_source(&self, src: &str) -> SourceParser {
+        let table_name = sanitize_table_name(src);
+        let find = |name: &String| *name == src || *name == table_name;
+
+        SourceParser {
+            line_format: self
+                .source_line_formats
+                .iter()
+                .find(|(name, _)| find(name))
+                .map(|(_, line_format)| line_format.clone())
+                .unwrap_or_else(|| self.line_format.clone()),
+            prefix: self
+                .source_prefixes
+                .iter()
+                .find(|(name, _)| find(name))
+                .map(|(_, prefix)| prefix.clone())
+                .or_else(|| self.prefix.clone()),
+            multiline: self
+                .source_multilines
+                .iter()
+                .find(|(name, _)| find(name))
+                .map(|(_, multiline)| multiline.clone())
+                .or_else(|| self.multiline.clone()),
+        }
+    }
+}
+
+/// Separates the name of a command source from its command.
+const SOURCE_NAME_SEPARATOR: &str = ":=";
+/// Also separates the name of a command source from its command, unless the text reads as an environment assignment.
+const SHORT_SOURCE_NAME_SEPARATOR: char = '=';
+
+/// A command source as given to `--srcs`.
+struct CommandSource {
+    /// The name given to the source with `<name>=<command>` or `<name>:=<command>`.
+    alias: Option<String>,
+    command: String,
+}
+
+impl CommandSource {
+    /// Splits `<name>=<command>` or `<name>:=<command>` into its parts, other text is a command without a name.
+    fn parse(src: &str) -> Result<Self, String> {
+        let (alias, command) = match split_source_name(src) {
+            Some(split) => split,
+            None => {
+                return Ok(CommandSource {
+                    alias: None,
+                    command: src.to_string(),
+                })
+            }
+        };
+
+        if !is_valid_source_name(alias) {
+            return Err(format!(
+                "invalid source name {alias}, expected letters, digits and underscores starting with a letter"
+            ));
+        }
+        if command.trim().is_empty() {
+            return Err(format!("source {alias} has no command"));
+        }
+        Ok(CommandSource {
+            alias: Some(alias.to_string()),
+            command: command.to_string(),
+        })
+    }
+
+    /// The name `--source-*` overrides refer to the source by, its alias if it has one.
+    fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.command)
+    }
+}
+
+/// Splits the name off `src`, if it has one.
+///
+/// A `:=` before the first whitespace always separates a name. A `=` only does when the name is valid and the text does not read
+/// as a shell command starting with environment assignments: the name is not all uppercase, like `LEVEL=debug ./app`,
+/// and the command does not start with an assignment itself, like `web=LEVEL=debug ./app`.
+fn split_source_name(src: &str) -> Option<(&str, &str)> {
+    if let Some((alias, command)) = src.split_once(SOURCE_NAME_SEPARATOR) {
+        if !alias.is_empty() && !alias.contains(char::is_whitespace) {
+            return Some((alias, command));
+        }
+    }
+
+    let (alias, command) = src.split_once(SHORT_SOURCE_NAME_SEPARATOR)?;
+    let assignment = !alias.chars().any(|c| c.is_ascii_lowercase())
+        || command
+            .split_whitespace()
+            .next()
+            .and_then(|word| word.split_once('='))
+            .is_some_and(|(variable, _)| is_variable_name(variable));
+    (is_valid_source_name(alias) && !assignment).then_some((alias, command))
+}
+
+/// Returns whether `name` is letters, digits and underscores starting with a letter.
+fn is_valid_source_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns whether `name` can be assigned to in a shell, letters, digits and underscores not starting with a digit.
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The flattening options of every source, the global options plus the `--source-*` overrides naming the source.
+struct SourceOptions {
+    global: IngestOptions,
+    source_arrays: Vec<(String, ArrayRule)>,
+    source_includes: Vec<(String, glob::Pattern)>,
+    source_excludes: Vec<(String, glob::Pattern)>,
+    source_max_depths: Vec<(String, usize)>,
+    source_timestamps: Vec<(String, TimestampRule)>,
+}
+
+impl SourceOptions {
+    /// Returns the options for `src`, which is matched both as given on the command line and as its table name.
+    fn for_source(&self, src: &str) -> IngestOptions {
+        let table_name = sanitize_table_name(src);
+        let find = |name: &String| *name == src || *name == table_name;
+
+        let mut options = self.global.clone();
+        self.source_arrays
+            .iter()
+            .filter(|(name, _)| find(name))
+            .for_each(|(_, rule)| options.arrays.apply(rule));
+        options.include.extend(
+            self.source_includes
+                .iter()
+                .filter(|(name, _)| find(name))
+                .map(|(_, glob)| glob.clone()),
+        );
+        options.exclude.extend(
+            self.source_excludes
+                .iter()
+                .filter(|(name, _)| find(name))
+                .map(|(_, glob)| glob.clone()),
+        );
+        if let Some((_, max_depth)) = self.source_max_depths.iter().find(|(name, _)| find(name)) {
+            options.max_depth = Some(*max_depth);
+        }
+        if let Some((_, timestamp)) = self.source_timestamps.iter().find(|(name, _)| find(name)) {
+            options.timestamp = timestamp.clone();
+        }
+        options
+    }
 }
 
 /// Represents the different modes the application can run in.
@@ -30,9 +516,9 @@ pub enum Mode {
         /// Common arguments used across different modes.
         #[clap(flatten)]
         common_args: CommonArgs,
-        /// Arguments specific to the noninteractive mode.
+        /// Arguments specific to the noninteractive mode, boxed as they dwarf the other modes.
         #[clap(flatten)]
-        args: NoninteractiveArgs,
+        args: Box<NoninteractiveArgs>,
     },
 }
 
@@ -67,33 +553,312 @@ pub fn cli_arg_parser() -> CliArgs {
 /// # Arguments
 ///
 /// * `shared_connection` - An Arc-wrapped Mutex-protected SQLite Connection that is shared across the application.
-/// * `srcs` - A vector of strings representing the data sources to ingest.
+/// * `args` - The noninteractive mode arguments describing the data sources to ingest.
 /// * `shared_signal` - An Arc-wrapped SharedState used for inter-thread communication.
 ///
 /// # Behavior
 ///
-/// This function iterates over each data source in `srcs`, and attempts to add it using the `add_src` function.
-/// If adding a source fails, an error message is printed to stderr.
+/// This function iterates over each data source in `args`, and attempts to add it using the matching `add_*` function.
+/// If adding a source fails, an error message is printed to stderr. If the parsers, options or source names are invalid no source is added and the session is stopped.
 pub fn noninteractive_mode(
     shared_connection: Arc<Mutex<Connection>>,
-    srcs: Vec<String>,
+    args: NoninteractiveArgs,
     shared_signal: Arc<SharedState>,
 ) {
-    for child in srcs {
-        println!("Adding data ingestion source {}", child);
+    let parsers = match args.source_parsers() {
+        Ok(parsers) => parsers,
+        Err(e) => {
+            eprintln!("Invalid parser configuration: {}", e);
+            shared_signal.stop();
+            return;
+        }
+    };
+
+    let options = match args.source_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Invalid ingestion options: {}", e);
+            shared_signal.stop();
+            return;
+        }
+    };
+
+    let command_sources = match args.command_sources() {
+        Ok(command_sources) => command_sources,
+        Err(e) => {
+            eprintln!("Invalid data ingestion source: {}", e);
+            shared_signal.stop();
+            return;
+        }
+    };
+
+    // every source of the session adds its table to the same all_logs view
+    let view_catalog = Arc::new(Mutex::new(ViewCatalog::default()));
+
+    if args.reads_stdin() {
+        println!("Adding standard input ingestion source");
+
+        if let Err(e) = add_stdin_src(
+            parsers.for_source(STDIN_SRC),
+            options.for_source(STDIN_SRC),
+            Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
+            shared_signal.clone(),
+        ) {
+            eprintln!(
+                "Adding standard input ingestion source failed due to {:?}",
+                e
+            );
+        } else {
+            println!("Standard input ingestion source added successfully");
+        }
+    }
+
+    for child in command_sources {
+        let name = child.name();
+        println!("Adding data ingestion source {}", name);
 
         if let Err(e) = add_src(
-            &child,
+            &child.command,
+            child.alias.as_deref(),
+            parsers.for_source(name),
+            args.stderr,
+            options.for_source(name),
             Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
             shared_signal.clone(),
         ) {
             eprintln!(
                 "Adding data ingestion source {} failed due to {:?}",
-                child, e
+                name, e
+            );
+            continue;
+        }
+
+        println!("Data ingestion source {} added successfully", name);
+    }
+
+    for file in args.files {
+        println!("Adding file ingestion source {}", file);
+
+        if let Err(e) = add_file_src(
+            &file,
+            args.include_rotated,
+            parsers.for_source(&file),
+            options.for_source(&file),
+            Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
+            shared_signal.clone(),
+        ) {
+            eprintln!(
+                "Adding file ingestion source {} failed due to {:?}",
+                file, e
+            );
+            continue;
+        }
+
+        println!("File ingestion source {} added successfully", file);
+    }
+
+    for pattern in args.globs {
+        println!("Adding glob ingestion source {}", pattern);
+
+        if let Err(e) = add_glob_src(
+            &pattern,
+            parsers.for_source(&pattern),
+            options.for_source(&pattern),
+            Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
+            shared_signal.clone(),
+        ) {
+            eprintln!(
+                "Adding glob ingestion source {} failed due to {:?}",
+                pattern, e
             );
             continue;
         }
 
-        println!("Data ingestion source {} added successfully", child);
+        println!("Glob ingestion source {} added successfully", pattern);
+    }
+
+    if args.syslog_udp.is_some() || args.syslog_tcp.is_some() {
+        println!("Adding syslog ingestion source");
+
+        if let Err(e) = add_syslog_src(
+            args.syslog_udp.as_deref(),
+            args.syslog_tcp.as_deref(),
+            options.for_source(SYSLOG_SRC),
+            Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
+            shared_signal.clone(),
+        ) {
+            eprintln!("Adding syslog ingestion source failed due to {:?}", e);
+        } else {
+            println!("Syslog ingestion source added successfully");
+        }
+    }
+
+    if args.docker {
+        println!("Adding docker ingestion source");
+
+        let added = ContainerFilter::parse(&args.docker_filter)
+            .map_err(|e| e.into())
+            .and_then(|filter| {
+                add_docker_src(
+                    &args.docker_socket,
+                    filter,
+                    parsers.for_source(DOCKER_SRC),
+                    options.for_source(DOCKER_SRC),
+                    Arc::clone(&shared_connection),
+                    Arc::clone(&view_catalog),
+                    shared_signal.clone(),
+                )
+            });
+        if let Err(e) = added {
+            eprintln!("Adding docker ingestion source failed due to {:?}", e);
+        } else {
+            println!("Docker ingestion source added successfully");
+        }
+    }
+
+    if let Some(addr) = args.http {
+        println!("Adding HTTP ingestion source {}", addr);
+
+        if let Err(e) = add_http_src(
+            &addr,
+            options.global.clone(),
+            Arc::clone(&shared_connection),
+            Arc::clone(&view_catalog),
+            shared_signal.clone(),
+        ) {
+            eprintln!(
+                "Adding HTTP ingestion source {} failed due to {:?}",
+                addr, e
+            );
+        } else {
+            println!("HTTP ingestion source {} added successfully", addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_sources_are_named_unless_they_read_as_environment_assignments() {
+        for src in ["web=docker logs web -f", "web:=docker logs web -f"] {
+            let named = CommandSource::parse(src).unwrap();
+            assert_eq!(named.alias.as_deref(), Some("web"));
+            assert_eq!(named.command, "docker logs web -f");
+        }
+        let named = CommandSource::parse("LOGS:=LEVEL=debug ./app").unwrap();
+        assert_eq!(named.alias.as_deref(), Some("LOGS"));
+        assert_eq!(named.command, "LEVEL=debug ./app");
+
+        for command in [
+            "LEVEL=debug ./app",
+            "web=LEVEL=debug ./app",
+            "./app --level=debug",
+            "./app a:=b",
+        ] {
+            let unnamed = CommandSource::parse(command).unwrap();
+            assert_eq!(unnamed.alias, None);
+            assert_eq!(unnamed.command, command);
+        }
+        assert!(CommandSource::parse("1web:=./app").is_err());
+        assert!(CommandSource::parse("web:= ").is_err());
+        assert!(CommandSource::parse("web= ").is_err());
+    }
+
+    #[test]
+    fn test_syslog_takes_flattening_overrides_but_not_parser_ones() {
+        let args = |args: &[&str]| {
+            NoninteractiveArgs::try_parse_from(
+                ["logparsely", "--syslog-udp", "127.0.0.1:5514"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap()
+        };
+
+        let options = args(&["--source-include", "syslog=msg"])
+            .source_options()
+            .unwrap();
+        assert_eq!(
+            options.for_source(SYSLOG_SRC).include,
+            vec![glob::Pattern::new("msg").unwrap()]
+        );
+        assert!(args(&["--source-parser", "syslog=json"])
+            .source_parsers()
+            .is_err());
+    }
+
+    #[test]
+    fn test_overrides_cannot_name_http_sources() {
+        let args = NoninteractiveArgs::try_parse_from([
+            "logparsely",
+            "--http",
+            "127.0.0.1:9880",
+            "--source-include",
+            "web=msg",
+        ])
+        .unwrap();
+        assert!(args.source_options().is_err());
+    }
+
+    #[test]
+    fn test_max_depth_must_keep_the_top_level() {
+        let options = |args: &[&str]| {
+            NoninteractiveArgs::try_parse_from(["logparsely"].iter().chain(args))
+                .unwrap()
+                .source_options()
+        };
+        assert!(options(&["--max-depth", "0"]).is_err());
+        assert!(options(&["-s", "app", "--source-max-depth", "app=0"]).is_err());
+        assert!(options(&[
+            "-s",
+            "app",
+            "--max-depth",
+            "1",
+            "--source-max-depth",
+            "app=2"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_overrides_name_a_source_even_when_it_holds_an_equals_sign() {
+        let args = NoninteractiveArgs::try_parse_from([
+            "logparsely",
+            "-s",
+            "LEVEL=debug ./app",
+            "-f",
+            "/var/log/api.log",
+            "--source-arrays",
+            "LEVEL=debug ./app=request.errors=rows",
+            "--source-max-depth",
+            "LEVEL=debug ./app=2",
+            "--source-timestamp",
+            "_var_log_api_log=ts:epoch_ms",
+        ])
+        .unwrap();
+        let options = args.source_options().unwrap();
+        assert_eq!(
+            options.source_max_depths,
+            vec![("LEVEL=debug ./app".to_string(), 2)]
+        );
+        assert_eq!(options.source_arrays[0].0, "LEVEL=debug ./app");
+        assert_eq!(options.source_timestamps[0].0, "_var_log_api_log");
+
+        let unmatched = |flag: &str, spec: &str| {
+            NoninteractiveArgs::try_parse_from(["logparsely", "-s", "./app", flag, spec])
+                .unwrap()
+                .source_parsers()
+                .is_err()
+        };
+        assert!(unmatched("--source-parser", "./api=json"));
+        assert!(unmatched("--source-parser", "json"));
+        assert!(!unmatched("--source-parser", "./app=json"));
     }
 }
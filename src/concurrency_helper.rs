@@ -4,6 +4,7 @@
 ///
 /// This module defines a `SharedState` struct that can be used to manage shared state signal in a concurrent context.
 use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
 /// A semaphore-like structure for managing shared state across multiple threads while supporting signaling and awaiting conditions across threads.
 ///
@@ -51,6 +52,25 @@ impl SharedState {
         }
     }
 
+    /// Returns whether the stop signal has been set, without blocking.
+    ///
+    /// This is used by sources that poll (e.g. file tailers) rather than being killed by a monitor thread.
+    pub fn is_stopped(&self) -> bool {
+        *self.should_stop.lock().unwrap()
+    }
+
+    /// Waits for the stop signal for at most `timeout`, returns `true` if the signal was set.
+    ///
+    /// This doubles as an interruptible sleep for polling loops, so they wake up immediately on shutdown.
+    pub fn wait_for_stop_signal_timeout(&self, timeout: Duration) -> bool {
+        let should_stop = self.should_stop.lock().unwrap();
+        let (should_stop, _) = self
+            .cnd
+            .wait_timeout_while(should_stop, timeout, |stop| !*stop)
+            .unwrap();
+        *should_stop
+    }
+
     /// Increments the child counter.
     ///
     /// This function is typically called when a new child thread is started.
@@ -0,0 +1,583 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides Docker container ingestion functionality.
+///
+/// This module talks to the Docker Engine API over its unix socket: it lists the containers matching a filter,
+/// follows the logs of each of them, and demultiplexes the stdout/stderr framing Docker uses for containers without a TTY.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::concurrency_helper::SharedState;
+use crate::ingestion::{SourceLine, STREAM_COL};
+
+/// Where the Docker Engine listens by default.
+pub const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+/// Column recording the full id of the container a row was logged by.
+pub const CONTAINER_ID_COL: &str = "_container_id";
+/// Column recording the name of the container a row was logged by, without Docker's leading `/`.
+pub const CONTAINER_NAME_COL: &str = "_container_name";
+/// Column recording the image of the container a row was logged by.
+pub const CONTAINER_IMAGE_COL: &str = "_container_image";
+/// Prefix of the columns recording the container's labels, e.g. `_container_label.com.docker.compose.service`.
+pub const CONTAINER_LABEL_COL_PREFIX: &str = "_container_label.";
+
+const CONTAINER_POLL_INTERVAL_MS: u64 = 2000;
+const FRAME_HEADER_LEN: usize = 8;
+
+/// Represents errors that can occur when talking to the Docker Engine API.
+///
+/// # Variants
+///
+/// * `Io` - Represents an error that occurred while connecting to or reading from the socket.
+/// * `Api` - Represents a non-success HTTP status returned by the engine, along with the response body.
+/// * `Parse` - Represents a response that could not be understood.
+#[derive(Debug)]
+pub enum DockerError {
+    Io(io::Error),
+    Api(u16, String),
+    Parse(String),
+}
+
+impl fmt::Display for DockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerError::Io(e) => write!(f, "{e}"),
+            DockerError::Api(status, message) => {
+                write!(f, "the engine answered {status}: {message}")
+            }
+            DockerError::Parse(message) => write!(f, "unexpected response: {message}"),
+        }
+    }
+}
+
+impl From<io::Error> for DockerError {
+    fn from(e: io::Error) -> Self {
+        DockerError::Io(e)
+    }
+}
+
+/// Selects the containers to follow, with the same semantics as `docker ps --filter`.
+///
+/// Several names match any of them, several labels must all match. An empty filter matches every running container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerFilter {
+    /// Container names (or name substrings, as Docker matches them).
+    pub names: Vec<String>,
+    /// Labels as `key` or `key=value`.
+    pub labels: Vec<String>,
+}
+
+impl ContainerFilter {
+    /// Parses `name=<name>` and `label=<key>[=<value>]` filter expressions.
+    pub fn parse(exprs: &[String]) -> Result<Self, String> {
+        let mut filter = ContainerFilter::default();
+        for expr in exprs {
+            match expr.split_once('=') {
+                Some(("name", name)) if !name.is_empty() => filter.names.push(name.to_string()),
+                Some(("label", label)) if !label.is_empty() => {
+                    filter.labels.push(label.to_string())
+                }
+                _ => {
+                    return Err(format!(
+                    "invalid docker filter {expr}, expected name=<name> or label=<key>[=<value>]"
+                ))
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The filter encoded as the `filters` query parameter of the Engine API.
+    fn query_param(&self) -> String {
+        let mut filters = serde_json::Map::new();
+        if !self.names.is_empty() {
+            filters.insert("name".to_string(), json!(self.names));
+        }
+        if !self.labels.is_empty() {
+            filters.insert("label".to_string(), json!(self.labels));
+        }
+        percent_encode(&Value::Object(filters).to_string())
+    }
+}
+
+/// A running container, as listed by the Engine API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: HashMap<String, String>,
+}
+
+impl Container {
+    /// The columns every row logged by this container carries.
+    fn tags(&self) -> HashMap<String, String> {
+        let mut tags = HashMap::from([
+            (CONTAINER_ID_COL.to_string(), self.id.clone()),
+            (CONTAINER_NAME_COL.to_string(), self.name.clone()),
+            (CONTAINER_IMAGE_COL.to_string(), self.image.clone()),
+        ]);
+        for (key, value) in &self.labels {
+            tags.insert(format!("{CONTAINER_LABEL_COL_PREFIX}{key}"), value.clone());
+        }
+        tags
+    }
+}
+
+/// Lists the running containers matching `filter`.
+pub fn list_containers(
+    socket: &Path,
+    filter: &ContainerFilter,
+) -> Result<Vec<Container>, DockerError> {
+    let body = get_json(
+        socket,
+        &format!("/containers/json?filters={}", filter.query_param()),
+    )?;
+    let entries = body
+        .as_array()
+        .ok_or_else(|| DockerError::Parse("expected a list of containers".to_string()))?;
+
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let id = entry["Id"].as_str()?.to_string();
+            let name = entry["Names"][0]
+                .as_str()
+                .map(|name| name.trim_start_matches('/').to_string())
+                .unwrap_or_else(|| id.chars().take(12).collect());
+            let labels = entry["Labels"]
+                .as_object()
+                .map(|labels| {
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.as_str().unwrap_or("").to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(Container {
+                id,
+                name,
+                image: entry["Image"].as_str().unwrap_or("").to_string(),
+                labels,
+            })
+        })
+        .collect())
+}
+
+/// Polls the engine for containers matching `filter` until the stop signal is set, following the logs of every new one.
+///
+/// Containers running when the watch starts are read from the beginning of their logs.
+/// A container that stops and starts again is picked up again from where its previous log stream ended.
+pub fn watch_containers(
+    socket: PathBuf,
+    filter: ContainerFilter,
+    sender: Sender<SourceLine>,
+    signal: Arc<SharedState>,
+) {
+    let followed: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    let resume_from: Arc<Mutex<HashMap<String, u64>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    loop {
+        match list_containers(&socket, &filter) {
+            Ok(containers) => {
+                for container in containers {
+                    let newly_followed = match followed.lock() {
+                        Ok(mut followed) => followed.insert(container.id.clone()),
+                        Err(_) => false,
+                    };
+                    if !newly_followed {
+                        continue;
+                    }
+
+                    println!(
+                        "Following logs of container {} ({})",
+                        container.name, container.image
+                    );
+                    let since = resume_from
+                        .lock()
+                        .ok()
+                        .and_then(|resume_from| resume_from.get(&container.id).copied());
+
+                    // log streams block on the socket, like the syslog listeners they are detached and die with the process
+                    thread::spawn({
+                        let socket = socket.clone();
+                        let sender = sender.clone();
+                        let followed = Arc::clone(&followed);
+                        let resume_from = Arc::clone(&resume_from);
+                        move || {
+                            if let Err(e) = follow_logs(&socket, &container, since, &sender) {
+                                eprintln!(
+                                    "Error following logs of container {}: {}",
+                                    container.name, e
+                                );
+                            }
+                            if let Ok(mut resume_from) = resume_from.lock() {
+                                resume_from.insert(container.id.clone(), unix_now());
+                            }
+                            if let Ok(mut followed) = followed.lock() {
+                                followed.remove(&container.id);
+                            }
+                        }
+                    });
+                }
+            }
+            Err(e) => eprintln!("Error listing docker containers: {}", e),
+        }
+
+        if signal.wait_for_stop_signal_timeout(Duration::from_millis(CONTAINER_POLL_INTERVAL_MS)) {
+            return;
+        }
+    }
+}
+
+/// Follows the logs of `container` until its log stream ends, sending every line into `sender`.
+fn follow_logs(
+    socket: &Path,
+    container: &Container,
+    since: Option<u64>,
+    sender: &Sender<SourceLine>,
+) -> Result<(), DockerError> {
+    // only containers without a TTY multiplex their stdout and stderr
+    let inspect = get_json(socket, &format!("/containers/{}/json", container.id))?;
+    let tty = inspect["Config"]["Tty"].as_bool().unwrap_or(false);
+
+    let mut path = format!(
+        "/containers/{}/logs?follow=1&stdout=1&stderr=1",
+        container.id
+    );
+    if let Some(since) = since {
+        path.push_str(&format!("&since={since}"));
+    }
+    let body = get(socket, &path)?;
+
+    let tags = container.tags();
+    let lines: Box<dyn Iterator<Item = (Option<&'static str>, String)>> = if tty {
+        Box::new(
+            BufReader::new(body)
+                .lines()
+                .map_while(Result::ok)
+                .map(|line| (None, line)),
+        )
+    } else {
+        Box::new(Demuxer::new(body).map(|(stream, line)| (Some(stream), line)))
+    };
+
+    for (stream, text) in lines {
+        let mut line_tags = tags.clone();
+        if let Some(stream) = stream {
+            line_tags.insert(STREAM_COL.to_string(), stream.to_string());
+        }
+        if sender
+            .send(SourceLine {
+                text,
+                tags: line_tags,
+            })
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits Docker's multiplexed log stream into lines tagged with the stream they were written to.
+///
+/// Every frame is an 8 byte header (stream type, three zero bytes, big endian payload length) followed by the payload.
+/// A line can span several frames, so a partial line is kept per stream until its newline arrives.
+pub struct Demuxer<R> {
+    inner: R,
+    ready: VecDeque<(&'static str, String)>,
+    partial: HashMap<&'static str, Vec<u8>>,
+    done: bool,
+}
+
+impl<R: Read> Demuxer<R> {
+    pub fn new(inner: R) -> Self {
+        Demuxer {
+            inner,
+            ready: VecDeque::new(),
+            partial: HashMap::new(),
+            done: false,
+        }
+    }
+
+    /// Reads the next frame into the partial line of its stream, `false` once the stream has ended.
+    fn read_frame(&mut self) -> bool {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        if self.inner.read_exact(&mut header).is_err() {
+            return false;
+        }
+        let stream = match header[0] {
+            2 => "stderr",
+            _ => "stdout",
+        };
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut payload = vec![0u8; len];
+        if self.inner.read_exact(&mut payload).is_err() {
+            return false;
+        }
+
+        let partial = self.partial.entry(stream).or_default();
+        partial.extend_from_slice(&payload);
+        while let Some(newline) = partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = partial.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.ready
+                .push_back((stream, line.trim_end_matches(['\n', '\r']).to_string()));
+        }
+        true
+    }
+}
+
+impl<R: Read> Iterator for Demuxer<R> {
+    type Item = (&'static str, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() && !self.done {
+            if !self.read_frame() {
+                self.done = true;
+                // flush lines that were not terminated before the stream ended
+                for (stream, partial) in self.partial.drain() {
+                    if !partial.is_empty() {
+                        self.ready
+                            .push_back((stream, String::from_utf8_lossy(&partial).to_string()));
+                    }
+                }
+            }
+        }
+        self.ready.pop_front()
+    }
+}
+
+fn get_json(socket: &Path, path: &str) -> Result<Value, DockerError> {
+    let mut body = String::new();
+    get(socket, path)?.read_to_string(&mut body)?;
+    serde_json::from_str(&body).map_err(|e| DockerError::Parse(e.to_string()))
+}
+
+/// Sends a `GET` request to the engine and returns the response body once the status is known to be a success.
+fn get(socket: &Path, path: &str) -> Result<Box<dyn Read + Send>, DockerError> {
+    let mut stream = connect(socket)?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| DockerError::Parse(format!("invalid status line {status_line:?}")))?;
+
+    let mut chunked = false;
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.parse::<u64>().ok();
+            }
+        }
+    }
+
+    let mut body: Box<dyn Read + Send> = match (chunked, content_length) {
+        (true, _) => Box::new(ChunkedReader::new(reader)),
+        (false, Some(len)) => Box::new(reader.take(len)),
+        (false, None) => Box::new(reader),
+    };
+
+    if !(200..300).contains(&status) {
+        let mut message = String::new();
+        body.read_to_string(&mut message)?;
+        return Err(DockerError::Api(status, message));
+    }
+    Ok(body)
+}
+
+#[cfg(unix)]
+fn connect(socket: &Path) -> io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(socket)
+}
+
+#[cfg(not(unix))]
+fn connect(_socket: &Path) -> io::Result<std::net::TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the docker source needs a unix socket",
+    ))
+}
+
+/// Decodes an HTTP/1.1 chunked body, which is how the engine streams followed logs.
+struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut size_line = String::new();
+            self.inner.read_line(&mut size_line)?;
+            let size = size_line.split(';').next().unwrap_or("").trim();
+            self.remaining = usize::from_str_radix(size, 16).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid chunk size {size_line:?}"),
+                )
+            })?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            self.done = true;
+            return Ok(0);
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            // every chunk's data is followed by a CRLF
+            let mut crlf = String::new();
+            self.inner.read_line(&mut crlf)?;
+        }
+        Ok(read)
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_demuxer_splits_streams_and_joins_partial_lines() {
+        let mut stream = frame(1, "{\"a\":1}\n{\"b\"");
+        stream.extend(frame(2, "oops\n"));
+        stream.extend(frame(1, ":2}\ntail"));
+
+        let lines: Vec<_> = Demuxer::new(stream.as_slice()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("stdout", "{\"a\":1}".to_string()),
+                ("stderr", "oops".to_string()),
+                ("stdout", "{\"b\":2}".to_string()),
+                ("stdout", "tail".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunked_reader() {
+        let body = "7\r\n{\"a\":1}\r\n3\r\n\n{}\r\n0\r\n\r\n";
+        let mut decoded = String::new();
+        ChunkedReader::new(body.as_bytes())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "{\"a\":1}\n{}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_containers_against_stub_engine() {
+        use std::os::unix::net::UnixListener;
+
+        let socket = std::env::temp_dir().join(format!("logparsely-{}.sock", uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&socket).unwrap();
+        let stub = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .unwrap();
+            let body =
+                r#"[{"Id":"abc123","Names":["/web"],"Image":"nginx:1","Labels":{"tier":"front"}}]"#;
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_line
+        });
+
+        let filter = ContainerFilter::parse(&["name=web".to_string()]).unwrap();
+        let containers = list_containers(&socket, &filter).unwrap();
+        let request_line = stub.join().unwrap();
+        std::fs::remove_file(&socket).unwrap();
+
+        assert!(request_line
+            .starts_with("GET /containers/json?filters=%7B%22name%22%3A%5B%22web%22%5D%7D "));
+        assert_eq!(
+            containers,
+            vec![Container {
+                id: "abc123".to_string(),
+                name: "web".to_string(),
+                image: "nginx:1".to_string(),
+                labels: HashMap::from([("tier".to_string(), "front".to_string())]),
+            }]
+        );
+    }
+}
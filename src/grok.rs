@@ -0,0 +1,324 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides regex and grok parsing functionality.
+///
+/// This module defines a `RegexParser` that turns the named captures of a regex into JSON objects,
+/// and a `GrokLibrary` of reusable patterns that grok expressions such as `%{IP:client} %{WORD:method}` are expanded with.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+/// Grok expressions referencing patterns nested deeper than this are rejected, it catches patterns that reference themselves.
+const MAX_GROK_DEPTH: usize = 32;
+/// Prefix of the capture group names generated for grok fields, field names such as `[http][method]` are not valid group names.
+const GROK_GROUP_PREFIX: &str = "grok__";
+
+/// The patterns every grok expression can use, a subset of Logstash's `grok-patterns` without look-around assertions.
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    ("INT", r"(?:[+-]?(?:[0-9]+))"),
+    ("BASE10NUM", r"(?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))"),
+    ("NUMBER", r"(?:%{BASE10NUM})"),
+    ("BASE16NUM", r"(?:0[xX])?[0-9a-fA-F]+"),
+    ("POSINT", r"\b(?:[1-9][0-9]*)\b"),
+    ("NONNEGINT", r"\b(?:[0-9]+)\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)",
+    ),
+    (
+        "IPV6",
+        r"(?:[0-9A-Fa-f]{0,4}:){2,7}(?:[0-9A-Fa-f]{1,4}|%{IPV4})?(?:%\w+)?",
+    ),
+    ("IP", r"(?:%{IPV6}|%{IPV4})"),
+    (
+        "HOSTNAME",
+        r"\b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?",
+    ),
+    ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("UNIXPATH", r"(?:/[\w_%!$@:.,+~-]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]+:|\\)(?:\\[^\\?*]*)+"),
+    ("PATH", r"(?:%{UNIXPATH}|%{WINPATH})"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+\-.]+"),
+    ("URIHOST", r"%{IPORHOST}(?::%{POSINT})?"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)[a-z]*\b",
+    ),
+    ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+    ("MONTHDAY", r"(?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])"),
+    ("DAY", r"\b(?:Mon|Tue|Wed|Thu|Fri|Sat|Sun)[a-z]*\b"),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+    ("MINUTE", r"(?:[0-5][0-9])"),
+    ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    (
+        "LOGLEVEL",
+        r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn(?:ing)?|WARN(?:ING)?|[Ee]rr(?:or)?|ERR(?:OR)?|[Cc]rit(?:ical)?|CRIT(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|[Ee]merg(?:ency)?|EMERG(?:ENCY)?)",
+    ),
+];
+
+/// The type a captured value is stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    String,
+    Int,
+    Float,
+}
+
+impl FieldType {
+    fn convert(&self, text: &str) -> Value {
+        let converted = match self {
+            FieldType::String => None,
+            FieldType::Int => text.parse::<i64>().ok().map(Value::from),
+            FieldType::Float => text.parse::<f64>().ok().map(Value::from),
+        };
+        converted.unwrap_or_else(|| Value::String(text.to_string()))
+    }
+}
+
+/// Named patterns that grok expressions are expanded with.
+///
+/// Starts out with the built-in patterns (`IP`, `WORD`, `HTTPDATE`, ...), files in Logstash's `NAME regex` format can add to or override them.
+#[derive(Debug, Clone)]
+pub struct GrokLibrary {
+    patterns: HashMap<String, String>,
+}
+
+impl Default for GrokLibrary {
+    fn default() -> Self {
+        GrokLibrary {
+            patterns: BUILTIN_PATTERNS
+                .iter()
+                .map(|(name, pattern)| (name.to_string(), pattern.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl GrokLibrary {
+    /// Adds the patterns of a file with one `NAME regex` definition per line, blank lines and `#` comments are skipped.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read grok patterns {}: {}", path.display(), e))?;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some((name, pattern)) if !pattern.trim().is_empty() => {
+                    self.add(name, pattern.trim());
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid grok pattern definition at {}:{}, expected NAME regex",
+                        path.display(),
+                        index + 1
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds or overrides a single pattern.
+    pub fn add(&mut self, name: &str, pattern: &str) {
+        self.patterns.insert(name.to_string(), pattern.to_string());
+    }
+
+    /// Expands every `%{SYNTAX}`, `%{SYNTAX:field}` and `%{SYNTAX:field:int|float}` reference of `expr` into plain regex,
+    /// recording the fields to capture in `fields`.
+    fn expand(
+        &self,
+        expr: &str,
+        depth: usize,
+        fields: &mut Vec<(String, FieldType)>,
+    ) -> Result<String, String> {
+        if depth > MAX_GROK_DEPTH {
+            return Err(format!(
+                "grok patterns are nested more than {MAX_GROK_DEPTH} levels deep, is one referencing itself?"
+            ));
+        }
+
+        let mut expanded = String::new();
+        let mut rest = expr;
+        while let Some(start) = rest.find("%{") {
+            expanded.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unclosed grok reference in {expr}"))?;
+            let reference = &rest[start + 2..end];
+            rest = &rest[end + 1..];
+
+            let mut parts = reference.splitn(3, ':');
+            let syntax = parts.next().unwrap_or("");
+            let pattern = self
+                .patterns
+                .get(syntax)
+                .ok_or_else(|| format!("unknown grok pattern {syntax}"))?;
+            let inner = self.expand(pattern, depth + 1, fields)?;
+
+            match parts.next() {
+                Some(field) if !field.is_empty() => {
+                    let field_type = match parts.next() {
+                        None | Some("string") => FieldType::String,
+                        Some("int") => FieldType::Int,
+                        Some("float") => FieldType::Float,
+                        Some(other) => {
+                            return Err(format!(
+                                "unknown grok type {other} for {field}, expected int or float"
+                            ))
+                        }
+                    };
+                    let group = format!("{GROK_GROUP_PREFIX}{}", fields.len());
+                    fields.push((normalize_field(field), field_type));
+                    expanded.push_str(&format!("(?P<{group}>{inner})"));
+                }
+                _ => expanded.push_str(&format!("(?:{inner})")),
+            }
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+/// Turns Logstash style field references (`[http][method]`) into our dotted column names (`http.method`).
+fn normalize_field(field: &str) -> String {
+    if field.starts_with('[') && field.ends_with(']') {
+        field[1..field.len() - 1].replace("][", ".")
+    } else {
+        field.to_string()
+    }
+}
+
+/// Parses lines with a regex, every named capture that participated in the match becomes a column.
+#[derive(Debug)]
+pub struct RegexParser {
+    regex: Regex,
+    /// Column name and type of the capture groups generated for grok fields.
+    fields: HashMap<String, (String, FieldType)>,
+}
+
+impl RegexParser {
+    /// Creates a parser from a regex with named capture groups, e.g. `^(?P<level>\w+) (?P<msg>.*)$`.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!("regex {pattern} has no named capture groups"));
+        }
+        Ok(RegexParser {
+            regex,
+            fields: HashMap::new(),
+        })
+    }
+
+    /// Creates a parser from a grok expression such as `%{IP:client} %{WORD:method} %{NUMBER:bytes:int}`,
+    /// expanded with the patterns of `library`. Like Logstash's grok, the expression is not anchored.
+    pub fn grok(expr: &str, library: &GrokLibrary) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        let pattern = library.expand(expr, 0, &mut fields)?;
+        let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!("grok expression {expr} captures no fields"));
+        }
+        Ok(RegexParser {
+            regex,
+            fields: fields
+                .into_iter()
+                .enumerate()
+                .map(|(index, field)| (format!("{GROK_GROUP_PREFIX}{index}"), field))
+                .collect(),
+        })
+    }
+
+    /// Parses a line into a JSON object, `None` if the regex does not match it.
+    pub fn parse(&self, line: &str) -> Option<Value> {
+        let captures = self.regex.captures(line)?;
+        let mut record = Map::new();
+        for name in self.regex.capture_names().flatten() {
+            let text = match captures.name(name) {
+                Some(text) => text.as_str(),
+                None => continue,
+            };
+            match self.fields.get(name) {
+                Some((field, field_type)) => {
+                    record.insert(field.clone(), field_type.convert(text));
+                }
+                None => {
+                    record.insert(name.to_string(), Value::String(text.to_string()));
+                }
+            }
+        }
+        Some(Value::Object(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_grok_expands_library_and_converts_types() {
+        let mut library = GrokLibrary::default();
+        library.add("METHOD", r"%{WORD}");
+        let parser = RegexParser::grok(
+            "%{IP:client} %{METHOD:[http][method]} %{URIPATHPARAM:request} %{NUMBER:bytes:int} %{NUMBER:took:float}",
+            &library,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parser.parse("10.0.0.1 GET /index.html?a=1 1043 0.25"),
+            Some(json!({
+                "client": "10.0.0.1",
+                "http.method": "GET",
+                "request": "/index.html?a=1",
+                "bytes": 1043,
+                "took": 0.25,
+            }))
+        );
+        assert_eq!(parser.parse("not an access line"), None);
+    }
+
+    #[test]
+    fn test_grok_rejects_unknown_and_recursive_patterns() {
+        let mut library = GrokLibrary::default();
+        library.add("LOOP", "%{LOOP}");
+        assert!(RegexParser::grok("%{NOPE:x}", &library).is_err());
+        assert!(RegexParser::grok("%{LOOP:x}", &library).is_err());
+        assert!(RegexParser::new(r"\w+").is_err());
+    }
+}
@@ -0,0 +1,524 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides an HTTP ingestion endpoint.
+///
+/// This module defines a `serve` function that accepts `POST /ingest/<source>` requests carrying NDJSON or a JSON array,
+/// and writes every object into the `<source>` table. OTLP/HTTP log exports posted to `/v1/logs` are accepted as well.
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use flate2::read::MultiGzDecoder;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::concurrency_helper::SharedState;
+use crate::ingestion::{
+    sanitize_table_name, write_json_object, IngestOptions, SourceMeta, SourceTable,
+};
+use crate::otlp::{decode_request, encode_response, log_records, Encoding, LOGS_ROUTE};
+use crate::unified_view::ViewCatalog;
+
+const INGEST_ROUTE: &str = "/ingest/";
+/// Table for OTLP logs whose resource has no usable `service.name`.
+const OTLP_DEFAULT_SOURCE: &str = "otlp";
+/// Prefix of the command HTTP and OTLP sources are recorded with in the `_sources` registry, e.g. `http:web`.
+const HTTP_COMMAND_PREFIX: &str = "http:";
+const RECV_POLL_INTERVAL_MS: u64 = 250;
+/// Bodies larger than this, as sent, are refused with 413.
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+/// Gzip bodies larger than this once decompressed are refused with 413, so a small body cannot inflate without bound.
+const MAX_DECOMPRESSED_BODY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The outcome of ingesting a single request body.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BatchResult {
+    /// Number of objects written to the table.
+    pub accepted: usize,
+    /// Number of entries that were not JSON objects or failed to be written.
+    pub rejected: usize,
+}
+
+/// Represents errors that can occur when reading a request body.
+///
+/// # Variants
+///
+/// * `TooLarge` - Represents a body larger than the given limit, as sent or once decompressed.
+/// * `Unreadable` - Represents an error that occurred while reading or decompressing the body.
+#[derive(Debug)]
+enum BodyError {
+    TooLarge(u64),
+    Unreadable(io::Error),
+}
+
+/// Serves ingestion requests until the stop signal is set.
+///
+/// Requests are handled one at a time on the calling thread, so every source table has a single writer.
+/// Tables are created the first time a source posts to them, and added to the `all_logs` view of `view_catalog`.
+pub fn serve(
+    server: Server,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) {
+    let mut tables: HashMap<String, SourceTable> = HashMap::new();
+
+    while !signal.is_stopped() {
+        let request = match server.recv_timeout(Duration::from_millis(RECV_POLL_INTERVAL_MS)) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Error receiving HTTP request: {}", e);
+                continue;
+            }
+        };
+
+        handle_request(
+            request,
+            &mut tables,
+            &options,
+            Arc::clone(&shared_connection),
+            &view_catalog,
+        );
+    }
+
+    for table in tables.values() {
+        table.finish(Arc::clone(&shared_connection));
+    }
+}
+
+fn handle_request(
+    mut request: Request,
+    tables: &mut HashMap<String, SourceTable>,
+    options: &IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: &Arc<Mutex<ViewCatalog>>,
+) {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    if path != LOGS_ROUTE && !path.starts_with(INGEST_ROUTE) {
+        return respond(
+            request,
+            404,
+            json!({"error": "expected POST /ingest/<source> or POST /v1/logs"}),
+        );
+    }
+    if request.method() != &Method::Post {
+        return respond(request, 405, json!({"error": "only POST is supported"}));
+    }
+
+    let too_large = |limit: u64| json!({"error": format!("body is larger than {limit} bytes")});
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > MAX_BODY_BYTES)
+    {
+        return respond(request, 413, too_large(MAX_BODY_BYTES));
+    }
+    let gzipped = header(&request, "Content-Encoding").is_some_and(|encoding| encoding == "gzip");
+    let body = match read_body(
+        request.as_reader(),
+        gzipped,
+        MAX_BODY_BYTES,
+        MAX_DECOMPRESSED_BODY_BYTES,
+    ) {
+        Ok(body) => body,
+        Err(BodyError::TooLarge(limit)) => return respond(request, 413, too_large(limit)),
+        Err(BodyError::Unreadable(e)) => {
+            return respond(
+                request,
+                400,
+                json!({"error": format!("failed to read body: {e}")}),
+            )
+        }
+    };
+
+    if path == LOGS_ROUTE {
+        return handle_otlp(
+            request,
+            &body,
+            tables,
+            options,
+            shared_connection,
+            view_catalog,
+        );
+    }
+
+    let source = match path.strip_prefix(INGEST_ROUTE) {
+        Some(source) if is_valid_source_name(source) => source.to_string(),
+        _ => return respond(request, 404, json!({"error": "invalid source name"})),
+    };
+    let body = String::from_utf8_lossy(&body);
+
+    let table = match table_for(
+        tables,
+        &source,
+        options,
+        Arc::clone(&shared_connection),
+        view_catalog,
+    ) {
+        Some(table) => table,
+        None => return respond(request, 500, json!({"error": "failed to create table"})),
+    };
+
+    match ingest_body(&body, options, table, shared_connection) {
+        Some(result) => respond(
+            request,
+            200,
+            json!({"accepted": result.accepted, "rejected": result.rejected}),
+        ),
+        None => respond(
+            request,
+            400,
+            json!({"error": "body is neither NDJSON nor a JSON array"}),
+        ),
+    }
+}
+
+/// Ingests an OTLP/HTTP logs export request, every service gets its own table named after its `service.name`.
+fn handle_otlp(
+    request: Request,
+    body: &[u8],
+    tables: &mut HashMap<String, SourceTable>,
+    options: &IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: &Arc<Mutex<ViewCatalog>>,
+) {
+    let encoding =
+        match header(&request, "Content-Type").and_then(|ct| Encoding::from_content_type(&ct)) {
+            Some(encoding) => encoding,
+            None => {
+                return respond(
+                    request,
+                    415,
+                    json!({"error": "expected application/json or application/x-protobuf"}),
+                )
+            }
+        };
+
+    let export_request = match decode_request(body, encoding) {
+        Ok(export_request) => export_request,
+        Err(e) => {
+            return respond(
+                request,
+                400,
+                json!({"error": format!("invalid export request: {e}")}),
+            )
+        }
+    };
+
+    let mut rejected = 0;
+    for log in log_records(&export_request) {
+        let source = log
+            .service_name
+            .filter(|name| is_valid_source_name(name))
+            .unwrap_or_else(|| OTLP_DEFAULT_SOURCE.to_string());
+
+        let written = table_for(
+            tables,
+            &source,
+            options,
+            Arc::clone(&shared_connection),
+            view_catalog,
+        )
+        .map(|table| {
+            write_json_object(
+                &log.record,
+                HashMap::new(),
+                options,
+                table,
+                Arc::clone(&shared_connection),
+            )
+        });
+        match written {
+            Some(Ok(())) => {}
+            Some(Err(op_err)) => {
+                eprintln!("Error inserting data into wide table: {}", op_err);
+                rejected += 1;
+            }
+            None => rejected += 1,
+        }
+    }
+
+    respond_bytes(
+        request,
+        200,
+        encoding.content_type(),
+        encode_response(rejected, encoding),
+    );
+}
+
+/// Returns the table of `source`, creating it the first time the source is seen.
+fn table_for<'a>(
+    tables: &'a mut HashMap<String, SourceTable>,
+    source: &str,
+    options: &IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: &Arc<Mutex<ViewCatalog>>,
+) -> Option<&'a mut SourceTable> {
+    if !tables.contains_key(source) {
+        let meta = SourceMeta::new(None);
+        let registered = meta.register(
+            &format!("{HTTP_COMMAND_PREFIX}{source}"),
+            None,
+            &sanitize_table_name(source),
+            Arc::clone(&shared_connection),
+        );
+        let table = registered.and_then(|table_name| {
+            SourceTable::new(
+                &table_name,
+                meta,
+                options,
+                shared_connection,
+                Arc::clone(view_catalog),
+            )
+        });
+        match table {
+            Ok(table) => {
+                tables.insert(source.to_string(), table);
+            }
+            Err(e) => {
+                eprintln!("Error in setting up SQLITE3 on your system {}", e);
+                return None;
+            }
+        }
+    }
+    tables.get_mut(source)
+}
+
+/// Reads a request body, decompressing it if the client sent `Content-Encoding: gzip` as OTLP exporters commonly do.
+///
+/// Reading stops as soon as the body turns out larger than `max_len` bytes as sent, or `max_decompressed_len` bytes once decompressed.
+fn read_body(
+    reader: impl Read,
+    gzipped: bool,
+    max_len: u64,
+    max_decompressed_len: u64,
+) -> Result<Vec<u8>, BodyError> {
+    // one byte past each limit tells a body of exactly the limit apart from a larger one
+    let mut raw = reader.take(max_len + 1);
+    let mut body = Vec::new();
+    let read = if gzipped {
+        MultiGzDecoder::new(&mut raw)
+            .take(max_decompressed_len + 1)
+            .read_to_end(&mut body)
+    } else {
+        raw.read_to_end(&mut body)
+    };
+    // a gzip stream cut off at the limit fails to decode, so the limits are checked first
+    if raw.limit() == 0 {
+        return Err(BodyError::TooLarge(max_len));
+    }
+    if body.len() as u64 > max_decompressed_len {
+        return Err(BodyError::TooLarge(max_decompressed_len));
+    }
+    read.map_err(BodyError::Unreadable)?;
+    Ok(body)
+}
+
+fn header(request: &Request, name: &str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+/// Writes every object of an NDJSON or JSON array body into `table`.
+///
+/// Returns `None` if the body starts like a JSON array but is not one, every NDJSON line is judged on its own.
+fn ingest_body(
+    body: &str,
+    options: &IngestOptions,
+    table: &mut SourceTable,
+    shared_connection: Arc<Mutex<Connection>>,
+) -> Option<BatchResult> {
+    let entries: Vec<Option<Value>> = if body.trim_start().starts_with('[') {
+        match serde_json::from_str::<Value>(body) {
+            Ok(Value::Array(values)) => values.into_iter().map(Some).collect(),
+            _ => return None,
+        }
+    } else {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<Value>(line).ok())
+            .collect()
+    };
+
+    let mut result = BatchResult::default();
+    for entry in entries {
+        let json_val = match entry {
+            Some(json_val) if json_val.is_object() => json_val,
+            _ => {
+                result.rejected += 1;
+                continue;
+            }
+        };
+
+        match write_json_object(
+            &json_val,
+            HashMap::new(),
+            options,
+            table,
+            Arc::clone(&shared_connection),
+        ) {
+            Ok(()) => result.accepted += 1,
+            Err(op_err) => {
+                eprintln!("Error inserting data into wide table: {}", op_err);
+                result.rejected += 1;
+            }
+        }
+    }
+
+    Some(result)
+}
+
+fn is_valid_source_name(source: &str) -> bool {
+    !source.is_empty()
+        && source
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn respond(request: Request, status: u16, body: Value) {
+    respond_bytes(
+        request,
+        status,
+        "application/json",
+        body.to_string().into_bytes(),
+    );
+}
+
+fn respond_bytes(request: Request, status: u16, content_type: &str, body: Vec<u8>) {
+    let header = Header::from_bytes("Content-Type", content_type).expect("content type is valid");
+    let response = Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Error responding to HTTP request: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::net::TcpStream;
+    use std::thread;
+
+    /// Sends a raw HTTP request to a loopback server, handles it and returns the status and JSON body of the response.
+    /// `head` is the request line and any extra headers, `Content-Length` is added unless it is among them.
+    fn exchange(
+        head: &str,
+        body: &[u8],
+        tables: &mut HashMap<String, SourceTable>,
+        shared_connection: &Arc<Mutex<Connection>>,
+    ) -> (u16, Value) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let length = if head.contains("Content-Length") {
+            String::new()
+        } else {
+            format!("\r\nContent-Length: {}", body.len())
+        };
+        let request = format!("{head}{length}\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let body = body.to_vec();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        handle_request(
+            server.recv().unwrap(),
+            tables,
+            &IngestOptions::default(),
+            Arc::clone(shared_connection),
+            &Arc::new(Mutex::new(ViewCatalog::default())),
+        );
+        let response = client.join().unwrap();
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn test_ndjson_and_array_batches_report_partial_accepts() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let mut tables = HashMap::new();
+
+        let ndjson = b"{\"a\":1}\nnot json\n\n[1]\n{\"b\":2}\n";
+        assert_eq!(
+            exchange("POST /ingest/web HTTP/1.1", ndjson, &mut tables, &conn),
+            (200, json!({"accepted": 2, "rejected": 2}))
+        );
+        let array = b"[{\"a\":3}, 4, {\"b\":\"x\"}]";
+        assert_eq!(
+            exchange(
+                "POST /ingest/web?batch=2 HTTP/1.1",
+                array,
+                &mut tables,
+                &conn
+            ),
+            (200, json!({"accepted": 2, "rejected": 1}))
+        );
+
+        let rows: i64 = conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM web", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 4);
+    }
+
+    #[test]
+    fn test_invalid_requests_get_client_errors() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let mut tables = HashMap::new();
+        let mut status = |head: &str, body: &[u8]| exchange(head, body, &mut tables, &conn).0;
+
+        assert_eq!(status("POST /elsewhere HTTP/1.1", b"{}"), 404);
+        assert_eq!(status("GET /ingest/web HTTP/1.1", b""), 405);
+        assert_eq!(status("POST /ingest/we%20b HTTP/1.1", b"{}"), 404);
+        assert_eq!(status("POST /ingest/web HTTP/1.1", b"[{\"a\":1},"), 400);
+        assert_eq!(status("POST /v1/logs HTTP/1.1", b"{}"), 415);
+        assert_eq!(
+            status(
+                "POST /ingest/web HTTP/1.1\r\nContent-Encoding: gzip",
+                b"not gzip"
+            ),
+            400
+        );
+    }
+
+    #[test]
+    fn test_oversized_bodies_are_refused() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let mut tables = HashMap::new();
+        // the declared length alone is enough to refuse the body, before any of it is read
+        let head = format!(
+            "POST /ingest/web HTTP/1.1\r\nContent-Length: {}",
+            MAX_BODY_BYTES + 1
+        );
+        let (status, _) = exchange(&head, b"", &mut tables, &conn);
+        assert_eq!(status, 413);
+
+        let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&[b' '; 1024]).unwrap();
+        let bomb = gz.finish().unwrap();
+        assert!(matches!(
+            read_body(&bomb[..], true, 1024, 1023),
+            Err(BodyError::TooLarge(1023))
+        ));
+        assert_eq!(read_body(&bomb[..], true, 1024, 1024).unwrap().len(), 1024);
+        assert!(matches!(
+            read_body(&[b'{'; 11][..], false, 10, 1024),
+            Err(BodyError::TooLarge(10))
+        ));
+    }
+}
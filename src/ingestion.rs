@@ -2,91 +2,834 @@
 
 /// Provides data ingestion functionality.
 ///
-/// This module defines a `add_src` function that is responsible for ingesting data from a single source's standard output.
+/// This module defines a `add_src` function that is responsible for ingesting data from a single source's standard output,
+/// `add_file_src`/`add_glob_src` functions that follow files natively, `add_stdin_src` for piped input, `add_docker_src` for containers,
+/// and `add_syslog_src`/`add_http_src` for network listeners.
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
+use chrono::Utc;
+use rusqlite::types::Value as SqlValue;
 use rusqlite::Connection;
 use serde_json::Value;
+use tiny_http::Server;
+use uuid::Uuid;
 
+use crate::access_log::{parse_access_log, AccessLogFormat};
+use crate::archive::{rotation_set, ArchiveReader};
 use crate::concurrency_helper::SharedState;
-use crate::storage::{EvolvingWideTable, RAW_UNPARSABLE_COL};
+use crate::docker::{list_containers, watch_containers, ContainerFilter};
+use crate::grok::{GrokLibrary, RegexParser};
+use crate::http_ingest::serve;
+use crate::logfmt::parse_logfmt;
+use crate::multiline::{assemble_records, Multiline};
+use crate::prefix::PrefixRule;
+use crate::storage::{
+    record_source_end, record_source_exit, register_source, ColumnAffinity, EvolvingWideTable,
+    StorageInsertionError, TableOptions, RAW_UNPARSABLE_COL, STDERR_TABLE_SUFFIX,
+};
+use crate::syslog::{parse_syslog, spawn_tcp_listener, spawn_udp_listener};
+use crate::tailing::{open_file_lines, watch_glob};
+use crate::timestamp::{format_timestamp, TimestampRule};
+use crate::unified_view::{ViewCatalog, ALL_LOGS_VIEW};
 
-/// Recursively flattens a JSON object into a map of string keys to string values.
+/// Table that lines read from standard input are ingested into.
+const STDIN_TABLE: &str = "stdin";
+/// Table that frames received by the syslog listeners are ingested into.
+const SYSLOG_TABLE: &str = "syslog";
+/// Table that the logs of followed containers are ingested into.
+const DOCKER_TABLE: &str = "docker";
+const STOP_POLL_INTERVAL_MS: u64 = 250;
+const REGEX_SPEC_PREFIX: &str = "regex:";
+const GROK_SPEC_PREFIX: &str = "grok:";
+
+/// The format lines of a source are expected in.
+///
+/// Lines that cannot be parsed in the expected format still end up in the table, in the `raw_unparsable_line` column.
+#[derive(Debug, Clone)]
+pub enum LineFormat {
+    /// JSON if the line is JSON, otherwise logfmt if every token of the line is a `key=value` pair.
+    Auto,
+    /// One JSON object per line, what most of our services log to standard out.
+    Json,
+    /// `key=value` pairs with optionally quoted values, what many Go services log.
+    Logfmt,
+    /// RFC 3164 or RFC 5424 syslog frames.
+    Syslog,
+    /// Lines matching a regex or grok expression, its named captures become the columns.
+    Regex(Arc<RegexParser>),
+    /// Apache/Nginx access log lines, in the common or combined format.
+    AccessLog(AccessLogFormat),
+}
+
+impl LineFormat {
+    /// Parses a line into a JSON value, `None` if the line is not in this format.
+    fn parse(&self, line: &str) -> Option<Value> {
+        match self {
+            LineFormat::Auto => serde_json::from_str::<Value>(line)
+                .ok()
+                .or_else(|| parse_logfmt(line, false)),
+            LineFormat::Json => serde_json::from_str::<Value>(line).ok(),
+            LineFormat::Logfmt => parse_logfmt(line, true),
+            LineFormat::Syslog => parse_syslog(line),
+            LineFormat::Regex(parser) => parser.parse(line),
+            LineFormat::AccessLog(format) => parse_access_log(line, *format),
+        }
+    }
+
+    /// Creates a format from its CLI spelling: `auto`, `json`, `logfmt`, `syslog`, `regex:<regex>`, `grok:<expression>`,
+    /// or the name of a built-in access log format such as `nginx-combined`, see `AccessLogFormat::from_name`.
+    ///
+    /// Grok expressions are expanded with the patterns of `grok_library`.
+    pub fn from_spec(spec: &str, grok_library: &GrokLibrary) -> Result<Self, String> {
+        if let Some(pattern) = spec.strip_prefix(REGEX_SPEC_PREFIX) {
+            return Ok(LineFormat::Regex(Arc::new(RegexParser::new(pattern)?)));
+        }
+        if let Some(expr) = spec.strip_prefix(GROK_SPEC_PREFIX) {
+            return Ok(LineFormat::Regex(Arc::new(RegexParser::grok(
+                expr,
+                grok_library,
+            )?)));
+        }
+        match spec {
+            "auto" => Ok(LineFormat::Auto),
+            "json" => Ok(LineFormat::Json),
+            "logfmt" => Ok(LineFormat::Logfmt),
+            "syslog" => Ok(LineFormat::Syslog),
+            other => AccessLogFormat::from_name(other)
+                .map(LineFormat::AccessLog)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown parser {other}, expected auto, json, logfmt, syslog, nginx-combined, nginx-common, \
+                         apache-combined, apache-common, regex:<regex> or grok:<expression>"
+                    )
+                }),
+        }
+    }
+}
+
+/// How the lines of a source are turned into records.
+#[derive(Debug, Clone)]
+pub struct SourceParser {
+    /// The format each record is parsed as.
+    pub line_format: LineFormat,
+    /// The prefix stripped from every line before lines are joined and parsed, e.g. the timestamp `docker logs -t` adds.
+    pub prefix: Option<PrefixRule>,
+    /// How lines are joined into records, `None` if every line is a record of its own.
+    pub multiline: Option<Multiline>,
+}
+
+impl SourceParser {
+    /// Creates a parser that reads every line as a record of its own.
+    pub fn new(line_format: LineFormat) -> Self {
+        SourceParser {
+            line_format,
+            prefix: None,
+            multiline: None,
+        }
+    }
+}
+
+/// Where the standard error of a command source goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StderrMode {
+    /// Into the same table as standard output, the `_stream` column tells them apart.
+    Merge,
+    /// Into a sibling table named `<table>_stderr`.
+    Table,
+    /// Not captured, it goes to logparsely's own standard error.
+    Inherit,
+}
+
+impl FromStr for StderrMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(StderrMode::Merge),
+            "table" => Ok(StderrMode::Table),
+            "inherit" => Ok(StderrMode::Inherit),
+            other => Err(format!(
+                "unknown stderr mode {other}, expected merge, table or inherit"
+            )),
+        }
+    }
+}
+
+/// Column recording which output stream of a command a row was read from, `stdout` or `stderr`.
+pub const STREAM_COL: &str = "_stream";
+const STDOUT_STREAM: &str = "stdout";
+const STDERR_STREAM: &str = "stderr";
+
+/// Column recording which file a row was read from, for sources that read from many files.
+pub const FILE_COL: &str = "_file";
+
+/// Column recording when a row was ingested, as an RFC 3339 UTC timestamp with microseconds.
+pub const INGESTED_AT_COL: &str = "_ingested_at";
+/// Column holding the event timestamp of a row in the same format, its ingestion time if the record has no timestamp. It is indexed.
+pub const TS_COL: &str = "_ts";
+/// Column numbering the rows a source wrote into a table from 1, so together with `_source_id` it identifies a row.
+pub const SEQ_COL: &str = "_seq";
+/// Column recording which source wrote a row, an id generated when the source is added.
+pub const SOURCE_ID_COL: &str = "_source_id";
+/// Column recording the process id of the command that printed a row.
+pub const PID_COL: &str = "_pid";
+/// The columns every row is stamped with.
+const METADATA_COLS: &[&str] = &[TS_COL, INGESTED_AT_COL, SEQ_COL, SOURCE_ID_COL, PID_COL];
+/// Appended to a record field named like a metadata column or tag, e.g. `_ts__record`, so the field is kept next to the column.
+const SHADOWED_FIELD_SUFFIX: &str = "__record";
+
+/// Identifies the source rows come from.
+#[derive(Debug, Clone)]
+pub struct SourceMeta {
+    /// Unique to every source of every run.
+    pub id: String,
+    /// The process id of a command source.
+    pub pid: Option<u32>,
+}
+
+impl SourceMeta {
+    /// Creates the identity of a newly added source.
+    pub fn new(pid: Option<u32>) -> Self {
+        SourceMeta {
+            id: Uuid::new_v4().to_string(),
+            pid,
+        }
+    }
+
+    /// Records the source in the `_sources` registry and returns the table it writes into, see `register_source`.
+    pub fn register(
+        &self,
+        command: &str,
+        alias: Option<&str>,
+        preferred_table: &str,
+        shared_connection: Arc<Mutex<Connection>>,
+    ) -> Result<String, Box<dyn Error>> {
+        let table_name =
+            register_source(shared_connection, &self.id, command, alias, preferred_table)
+                .map_err(|e| format!("failed to register source {command}: {}", e))?;
+        Ok(table_name)
+    }
+}
+
+/// A table a source writes into, every row it inserts is stamped with the ingestion metadata columns.
+pub struct SourceTable {
+    wide_table: EvolvingWideTable,
+    source: SourceMeta,
+    next_seq: i64,
+    timestamp: TimestampRule,
+    /// The columns of the `all_logs` view, shared by every source of the session.
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    /// The rows inserted so far, and how many of them hold a line that could not be parsed.
+    lines: u64,
+    unparsable_lines: u64,
+}
+
+impl SourceTable {
+    /// Opens the `table_name` table for `source`, creating it if it does not exist, and adds it to the `all_logs` view of `view_catalog`.
+    pub fn new(
+        table_name: &str,
+        source: SourceMeta,
+        options: &IngestOptions,
+        shared_connection: Arc<Mutex<Connection>>,
+        view_catalog: Arc<Mutex<ViewCatalog>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut wide_table = EvolvingWideTable::new(
+            table_name.to_string(),
+            options.table.clone(),
+            Arc::clone(&shared_connection),
+        )?;
+        wide_table.create_index(TS_COL, ColumnAffinity::Text, Arc::clone(&shared_connection))?;
+        refresh_view(&wide_table, &view_catalog, &shared_connection);
+
+        Ok(SourceTable {
+            wide_table,
+            source,
+            next_seq: 1,
+            timestamp: options.timestamp.clone(),
+            view_catalog,
+            lines: 0,
+            unparsable_lines: 0,
+        })
+    }
+
+    /// Stamps `row` with the ingestion metadata and inserts it along with its `children`, see `EvolvingWideTable::insert_data`.
+    ///
+    /// The event timestamp is read from `fields` if given, the whole record when `row` lacks some of its keys, else from `row`.
+    /// Fields of `row` named like a metadata column are moved aside rather than overwritten, see `shelve_shadowed_fields`.
+    fn insert(
+        &mut self,
+        mut row: HashMap<String, SqlValue>,
+        children: Vec<(String, HashMap<String, SqlValue>)>,
+        fields: Option<&HashMap<String, SqlValue>>,
+        shared_connection: Arc<Mutex<Connection>>,
+    ) -> Result<i64, StorageInsertionError> {
+        let ingested_at = format_timestamp(&Utc::now());
+        let ts = self
+            .timestamp
+            .extract(fields.unwrap_or(&row))
+            .unwrap_or_else(|| ingested_at.clone());
+        shelve_shadowed_fields(&mut row, METADATA_COLS.iter().copied());
+        row.insert(TS_COL.to_string(), SqlValue::Text(ts));
+        row.insert(INGESTED_AT_COL.to_string(), SqlValue::Text(ingested_at));
+        row.insert(SEQ_COL.to_string(), SqlValue::Integer(self.next_seq));
+        row.insert(
+            SOURCE_ID_COL.to_string(),
+            SqlValue::Text(self.source.id.clone()),
+        );
+        if let Some(pid) = self.source.pid {
+            row.insert(PID_COL.to_string(), SqlValue::Integer(i64::from(pid)));
+        }
+
+        let columns = self.wide_table.column_count();
+        let row_id = self
+            .wide_table
+            .insert_data(Arc::clone(&shared_connection), row, children)?;
+        self.next_seq += 1;
+        self.lines += 1;
+        if self.wide_table.column_count() != columns {
+            refresh_view(&self.wide_table, &self.view_catalog, &shared_connection);
+        }
+        Ok(row_id)
+    }
+
+    /// Adds the lines of this table to the source's counts in the `_sources` registry, and records when it ended.
+    pub fn finish(&self, shared_connection: Arc<Mutex<Connection>>) {
+        if let Err(e) = record_source_end(
+            shared_connection,
+            &self.source.id,
+            self.lines,
+            self.unparsable_lines,
+        ) {
+            eprintln!(
+                "Failed to record the end of source {}: {}",
+                self.source.id, e
+            );
+        }
+    }
+}
+
+/// Recreates the `all_logs` view so it picks up the columns of `table`.
+///
+/// A view that cannot be recreated is reported and left as it was, the row is kept regardless.
+fn refresh_view(
+    table: &EvolvingWideTable,
+    view_catalog: &Mutex<ViewCatalog>,
+    shared_connection: &Arc<Mutex<Connection>>,
+) {
+    let conn = match shared_connection.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to refresh the {ALL_LOGS_VIEW} view: {e}");
+            return;
+        }
+    };
+    let mut catalog = match view_catalog.lock() {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to refresh the {ALL_LOGS_VIEW} view: {e}");
+            return;
+        }
+    };
+    if let Err(e) = catalog.refresh(&conn, table.table_name(), table.columns()) {
+        eprintln!("Kept the {ALL_LOGS_VIEW} view as it was: {e}");
+    }
+}
+
+/// A single line produced by a source.
+///
+/// Besides the raw text, a source can attach columns that are added to the row the line ends up in (e.g. the file the line came from).
+pub struct SourceLine {
+    /// The raw line, without the trailing newline.
+    pub text: String,
+    /// Extra columns attached by the source.
+    pub tags: HashMap<String, String>,
+}
+
+impl SourceLine {
+    /// Creates a line without any extra columns.
+    pub fn new(text: String) -> Self {
+        SourceLine {
+            text,
+            tags: HashMap::new(),
+        }
+    }
+}
+
+/// How arrays in a record are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayMode {
+    /// One column per element, `tags[0]`, `tags[1]`, ...
+    #[default]
+    Index,
+    /// A single column holding the array as JSON text, queryable with SQLite's JSON functions (e.g. `json_each(tags)`).
+    Json,
+    /// One row per element in a child table named `<table>__<key path>`, linked to the `_row_id` of the record by its `_parent_id` column.
+    Rows,
+}
+
+impl FromStr for ArrayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(ArrayMode::Index),
+            "json" => Ok(ArrayMode::Json),
+            "rows" => Ok(ArrayMode::Rows),
+            other => Err(format!(
+                "unknown array mode {other}, expected index, json or rows"
+            )),
+        }
+    }
+}
+
+/// An array mode for every array, or for the arrays of a single key path, as `<mode>` or `<key path>=<mode>` on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArrayRule {
+    /// The key path the rule applies to, `None` for every array.
+    pub path: Option<String>,
+    pub mode: ArrayMode,
+}
+
+impl FromStr for ArrayRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('=') {
+            Some((path, mode)) => Ok(ArrayRule {
+                path: Some(path.to_string()),
+                mode: mode.parse()?,
+            }),
+            None => Ok(ArrayRule {
+                path: None,
+                mode: s.parse()?,
+            }),
+        }
+    }
+}
+
+/// The array mode of every key path, e.g. `tags` or `request.errors`.
+#[derive(Debug, Clone, Default)]
+pub struct ArrayModes {
+    /// The mode of arrays whose key path has no mode of its own.
+    pub default: ArrayMode,
+    /// Modes by key path, the keys of nested objects are joined with `.` and array indices are left out.
+    pub paths: HashMap<String, ArrayMode>,
+}
+
+impl ArrayModes {
+    /// Applies `rule`, overriding the mode previous rules set for the same arrays.
+    pub fn apply(&mut self, rule: &ArrayRule) {
+        match &rule.path {
+            Some(path) => {
+                self.paths.insert(path.clone(), rule.mode);
+            }
+            None => self.default = rule.mode,
+        }
+    }
+
+    fn for_path(&self, path: &str) -> ArrayMode {
+        self.paths.get(path).copied().unwrap_or(self.default)
+    }
+}
+
+/// Options for how parsed records are flattened into rows, shared by every source unless a source overrides them.
+#[derive(Debug, Clone, Default)]
+pub struct IngestOptions {
+    /// How many levels of JSON documents embedded in string values (e.g. `"message": "{\"user\":42}"`) are decoded
+    /// and flattened like nested objects, 0 keeps them as strings.
+    pub embedded_json_depth: usize,
+    /// How arrays are stored.
+    pub arrays: ArrayModes,
+    /// Globs on key paths (e.g. `http.request.*`) of the values that are kept, everything is kept if empty.
+    /// A matching object or array is kept along with everything below it.
+    pub include: Vec<glob::Pattern>,
+    /// Globs on key paths (e.g. `http.request.body.*`) of the values that are dropped along with everything below them, even if they are included.
+    pub exclude: Vec<glob::Pattern>,
+    /// Which field holds the event timestamp stored in `_ts`.
+    pub timestamp: TimestampRule,
+    /// How many levels deep keys may be, e.g. 2 flattens `http.status` but stores `http.request` as JSON text. `None` flattens every level.
+    pub max_depth: Option<usize>,
+    /// How rows are laid out in the tables of every source.
+    pub table: TableOptions,
+}
+
+impl IngestOptions {
+    /// Whether `include`, `exclude` or `max_depth` keep some keys of a record out of its row.
+    fn filters_keys(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty() || self.max_depth.is_some()
+    }
+
+    /// The same options, keeping every key of a record.
+    fn without_key_filters(&self) -> IngestOptions {
+        IngestOptions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_depth: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Column of a child row holding the position of its element in the array.
+pub const ARRAY_INDEX_COL: &str = "_index";
+/// Column of a child row holding its element, if the element is not an object.
+const ARRAY_VALUE_COL: &str = "value";
+
+/// A record flattened into the columns of its row, and the rows of its arrays stored in `ArrayMode::Rows`.
+#[derive(Debug, Default)]
+struct FlattenedRecord {
+    columns: HashMap<String, SqlValue>,
+    children: Vec<ChildRow>,
+}
+
+/// An element of an array stored in `ArrayMode::Rows`, flattened into its own row.
+#[derive(Debug)]
+struct ChildRow {
+    /// Key path of the array, names the child table.
+    path: String,
+    index: usize,
+    columns: HashMap<String, SqlValue>,
+}
+
+/// Recursively flattens a JSON object into a map of string keys to SQLite values.
 ///
 /// This function is used to transform a nested JSON object into a flat map. The keys in the map are the paths to the values in the JSON object.
-fn flatten_json(json: &Value) -> HashMap<String, String> {
-    let mut result = HashMap::new();
-    flatten_json_recursive(json, String::new(), &mut result);
-    result
+/// Strings are stored without their quotes, numbers as `INTEGER` or `REAL`, booleans as `1`/`0` and `null` as `NULL`.
+/// Arrays are stored according to `options.arrays`, arrays nested in the elements of a child row are stored as JSON instead of in further child rows.
+/// Key paths are filtered by `options.include` and `options.exclude`, and objects and arrays below `options.max_depth` are stored as JSON.
+fn flatten_json(json: &Value, options: &IngestOptions) -> FlattenedRecord {
+    let mut record = FlattenedRecord::default();
+    let root = Position {
+        key: String::new(),
+        path: String::new(),
+        depth: 0,
+        embedded_json_depth: options.embedded_json_depth,
+        included: options.include.is_empty(),
+    };
+    flatten_json_recursive(
+        json,
+        root,
+        options,
+        &mut record.columns,
+        Some(&mut record.children),
+    );
+    record
+}
+
+/// Where a value sits in the record being flattened.
+#[derive(Debug, Clone)]
+struct Position {
+    /// The column the value is stored in.
+    key: String,
+    /// The key path of the value, `key` without array indices, selects the array mode and is matched by the include/exclude globs.
+    path: String,
+    /// How many objects and arrays the value is nested in.
+    depth: usize,
+    /// How many more levels of embedded JSON may be decoded.
+    embedded_json_depth: usize,
+    /// Whether the value or one of the objects it is nested in matched an include glob.
+    included: bool,
+}
+
+impl Position {
+    /// The position of the field `field` of the object at this position.
+    fn field(&self, field: &str, options: &IngestOptions) -> Position {
+        let path = join_key(&self.path, field);
+        Position {
+            key: join_key(&self.key, field),
+            included: self.included || options.include.iter().any(|glob| glob.matches(&path)),
+            path,
+            depth: self.depth + 1,
+            embedded_json_depth: self.embedded_json_depth,
+        }
+    }
+
+    /// The position of an element of the array at this position, stored under `key`.
+    fn element(&self, key: String) -> Position {
+        Position {
+            key,
+            depth: self.depth + 1,
+            ..self.clone()
+        }
+    }
 }
 
-fn flatten_json_recursive(json: &Value, prefix: String, result: &mut HashMap<String, String>) {
+fn flatten_json_recursive(
+    json: &Value,
+    pos: Position,
+    options: &IngestOptions,
+    columns: &mut HashMap<String, SqlValue>,
+    mut children: Option<&mut Vec<ChildRow>>,
+) {
+    if !pos.path.is_empty() && options.exclude.iter().any(|glob| glob.matches(&pos.path)) {
+        return;
+    }
+    let is_container = json.is_object() || json.is_array();
+    if is_container
+        && options
+            .max_depth
+            .is_some_and(|max_depth| pos.depth >= max_depth)
+    {
+        if pos.included {
+            let pruned = without_excluded(json, &pos.path, options);
+            columns.insert(pos.key, SqlValue::Text(pruned.to_string()));
+        }
+        return;
+    }
+
     match json {
         Value::Object(obj) => {
-            for (key, value) in obj {
-                let new_prefix = if prefix.is_empty() {
-                    key.clone()
-                } else {
-                    format!("{prefix}.{key}")
-                };
-                flatten_json_recursive(value, new_prefix, result);
+            for (field, value) in obj {
+                flatten_json_recursive(
+                    value,
+                    pos.field(field, options),
+                    options,
+                    columns,
+                    children.as_deref_mut(),
+                );
             }
         }
-        Value::Array(arr) => {
-            for (index, value) in arr.iter().enumerate() {
-                let new_prefix = format!("{prefix}[{index}]");
-                flatten_json_recursive(value, new_prefix, result);
+        Value::Array(arr) => match (options.arrays.for_path(&pos.path), children) {
+            (ArrayMode::Index, mut children) => {
+                for (index, value) in arr.iter().enumerate() {
+                    flatten_json_recursive(
+                        value,
+                        pos.element(format!("{}[{index}]", pos.key)),
+                        options,
+                        columns,
+                        children.as_deref_mut(),
+                    );
+                }
             }
+            (ArrayMode::Rows, Some(children)) => {
+                for (index, value) in arr.iter().enumerate() {
+                    let mut row = HashMap::new();
+                    let element_key = if value.is_object() {
+                        String::new()
+                    } else {
+                        ARRAY_VALUE_COL.to_string()
+                    };
+                    flatten_json_recursive(
+                        value,
+                        pos.element(element_key),
+                        options,
+                        &mut row,
+                        None,
+                    );
+                    if !row.is_empty() {
+                        children.push(ChildRow {
+                            path: pos.path.clone(),
+                            index,
+                            columns: row,
+                        });
+                    }
+                }
+            }
+            _ if pos.included => {
+                let pruned = without_excluded(json, &pos.path, options);
+                columns.insert(pos.key, SqlValue::Text(pruned.to_string()));
+            }
+            _ => {}
+        },
+        Value::String(text) if pos.embedded_json_depth > 0 => match decode_embedded_json(text) {
+            Some(embedded) => {
+                let pos = Position {
+                    embedded_json_depth: pos.embedded_json_depth - 1,
+                    ..pos
+                };
+                flatten_json_recursive(&embedded, pos, options, columns, children)
+            }
+            None if pos.included => {
+                columns.insert(pos.key, SqlValue::Text(text.clone()));
+            }
+            None => {}
+        },
+        scalar if pos.included => {
+            columns.insert(pos.key, to_sql_value(scalar));
         }
-        fallback => {
-            result.insert(prefix, fallback.to_string());
-        }
+        _ => {}
     };
 }
 
-/// Ingests data from a single source's standard output.
+/// Returns a copy of the object or array at `path` without the fields whose key path is excluded, for storing it as JSON text.
+fn without_excluded(json: &Value, path: &str, options: &IngestOptions) -> Value {
+    match json {
+        Value::Object(obj) => Value::Object(
+            obj.iter()
+                .filter_map(|(field, value)| {
+                    let path = join_key(path, field);
+                    let excluded = options.exclude.iter().any(|glob| glob.matches(&path));
+                    (!excluded).then(|| (field.clone(), without_excluded(value, &path, options)))
+                })
+                .collect(),
+        ),
+        Value::Array(arr) => Value::Array(
+            arr.iter()
+                .map(|value| without_excluded(value, path, options))
+                .collect(),
+        ),
+        scalar => scalar.clone(),
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+/// Converts a JSON scalar into the SQLite value it is stored as.
+fn to_sql_value(json: &Value) -> SqlValue {
+    match json {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            // integers too large for SQLite's 64 bit integers keep all their digits as text, a float would round them
+            None if n.is_u64() => SqlValue::Text(n.to_string()),
+            None => n.as_f64().map_or(SqlValue::Null, SqlValue::Real),
+        },
+        Value::String(text) => SqlValue::Text(text.clone()),
+        nested => SqlValue::Text(nested.to_string()),
+    }
+}
+
+/// Decodes a string value holding a JSON object or array, such as the `log` field of Docker's json-file driver.
 ///
-/// This function is responsible for ingesting data from a single source's standard output. It reads the output line by line, transforms each line into a flat JSON object, and inserts the object into a SQLite database.
+/// Scalars are left alone so that strings like `"42"` or `"true"` keep their type.
+fn decode_embedded_json(text: &str) -> Option<Value> {
+    let trimmed = text.trim();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return None;
+    }
+    serde_json::from_str::<Value>(trimmed)
+        .ok()
+        .filter(|embedded| embedded.is_object() || embedded.is_array())
+}
+
+/// Ingests lines from a single source.
+///
+/// This function is responsible for ingesting the lines produced by a single source. It transforms each line into a flat JSON object, and inserts the object into a SQLite database.
+///
+/// Every row is stamped with the time it was ingested, its sequence number within the source, and the id of `source`, see `SourceTable`.
+///
+/// The function takes seven arguments:
+/// * `lines`: the lines produced by the source, ingestion stops when they run out (e.g. a channel whose senders are all dropped).
+/// * `parser`: how the lines are assembled into records and parsed.
+/// * `options`: how the records are flattened into rows.
+/// * `source`: the identity of the source, recorded on every row.
+/// * `src_name`: the table the source writes into, as returned by `SourceMeta::register`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+fn transformation<I>(
+    lines: I,
+    parser: SourceParser,
+    options: IngestOptions,
+    source: SourceMeta,
+    src_name: &str,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+) where
+    I: IntoIterator<Item = SourceLine>,
+    I::IntoIter: Send + 'static,
+{
+    let table_res = SourceTable::new(
+        src_name,
+        source,
+        &options,
+        Arc::clone(&shared_connection),
+        view_catalog,
+    );
+
+    let mut table = match table_res {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error in setting up SQLITE3 on your system {}", e);
+            return;
+        }
+    };
+
+    let lines: Box<dyn Iterator<Item = SourceLine> + Send> = match parser.prefix {
+        Some(prefix) => Box::new(lines.into_iter().map(move |line| prefix.extract(line))),
+        None => Box::new(lines.into_iter()),
+    };
+    let records: Box<dyn Iterator<Item = SourceLine>> = match parser.multiline {
+        Some(multiline) => Box::new(assemble_records(lines, multiline)),
+        None => lines,
+    };
+
+    for line in records {
+        match parser.line_format.parse(&line.text) {
+            Some(json_val) => {
+                if json_val.as_object().is_none() {
+                    eprintln!("JSON object expected, but found: {}", json_val);
+                    continue;
+                }
+
+                if let Err(op_err) = write_json_object(
+                    &json_val,
+                    line.tags,
+                    &options,
+                    &mut table,
+                    Arc::clone(&shared_connection),
+                ) {
+                    eprintln!("Error inserting data into wide table: {}", op_err);
+                };
+            }
+            None => write_non_json_line(line, &mut table, shared_connection.clone()),
+        }
+    }
+
+    table.finish(shared_connection);
+}
+
+/// Ingests data from a child process's standard output, and its standard error unless it is inherited.
 ///
-/// The function takes four arguments:
+/// The function takes nine arguments:
 /// * `child`: a `Child` process that is producing the data to ingest.
-/// * `src_name`: a string that identifies the data source.
+/// * `source`: the identity of the source, its exit status is recorded in the `_sources` registry once it is stopped.
+/// * `src_name`: the table the source writes into.
+/// * `parser`: how the lines of both streams are parsed.
+/// * `stderr_mode`: where the child's standard error goes, see `StderrMode`.
+/// * `options`: how the records are flattened into rows.
 /// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
 /// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
 ///
 /// The function spawns a monitor thread that kills the `child` process if the main thread signals to stop.
-fn transformation(
+#[allow(clippy::too_many_arguments)]
+fn ingest_child(
     mut child: Child,
+    source: SourceMeta,
     src_name: &str,
+    parser: SourceParser,
+    stderr_mode: StderrMode,
+    options: IngestOptions,
     shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
     signal: Arc<SharedState>,
 ) {
     let stdout = child.stdout.take().expect("Failed to capture stdout");
-    let reader = BufReader::new(stdout);
-    let lines = reader.lines();
-
-    let wide_table_res =
-        EvolvingWideTable::new(src_name.to_string(), Arc::clone(&shared_connection));
-
-    let mut wide_table = match wide_table_res {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Error in setting up SQLITE3 on your system {:?}", e);
-            signal.decr_and_notify_all_children_done_awaiters();
-            return;
-        }
-    };
+    let stderr = child.stderr.take();
 
     // since logaprsely creates the Process it is redirecting standardout from, it is responsible for killing it.
     // this monitor thread is responsible for killing the child process if the main thread signals to stop.
     let monitor = thread::spawn({
         let sig = Arc::clone(&signal);
+        let shared_connection = Arc::clone(&shared_connection);
+        let source_id = source.id.clone();
         move || {
             sig.wait_for_stop_signal();
 
@@ -101,36 +844,76 @@ fn transformation(
                     }
                 }
             }
+            if let Ok(status) = child.wait() {
+                if let Err(e) = record_source_exit(shared_connection, &source_id, status.code()) {
+                    eprintln!(
+                        "Failed to record the exit status of source {}: {}",
+                        source_id, e
+                    );
+                }
+            }
 
             sig.decr_and_notify_all_children_done_awaiters();
         }
     });
 
-    for line_res in lines {
-        let line = match line_res {
-            Err(_) => {
-                eprintln!("Error reading line");
-                continue;
-            }
-            Ok(line) => line,
-        };
-
-        match serde_json::from_str::<serde_json::Value>(&line) {
-            Ok(json_val) => {
-                if let None = json_val.as_object() {
-                    eprintln!("JSON object expected, but found: {}", json_val);
-                    continue;
+    match (stderr, stderr_mode) {
+        (Some(stderr), StderrMode::Merge) => {
+            let (sender, receiver) = mpsc::channel::<SourceLine>();
+            spawn_stream_reader(stdout, STDOUT_STREAM, sender.clone());
+            spawn_stream_reader(stderr, STDERR_STREAM, sender);
+            transformation(
+                receiver,
+                parser,
+                options,
+                source,
+                src_name,
+                shared_connection,
+                view_catalog,
+            );
+        }
+        (Some(stderr), StderrMode::Table) => {
+            let stderr_table = format!("{src_name}{STDERR_TABLE_SUFFIX}");
+            let stderr_transformation = thread::spawn({
+                let shared_connection = Arc::clone(&shared_connection);
+                let view_catalog = Arc::clone(&view_catalog);
+                let parser = parser.clone();
+                let options = options.clone();
+                let source = source.clone();
+                move || {
+                    transformation(
+                        read_stream_lines(stderr, STDERR_STREAM),
+                        parser,
+                        options,
+                        source,
+                        &stderr_table,
+                        shared_connection,
+                        view_catalog,
+                    )
                 }
-
-                let flattened_map = flatten_json(&json_val);
-                if let Err(op_err) =
-                    wide_table.insert_data(Arc::clone(&shared_connection), flattened_map)
-                {
-                    eprintln!("Error inserting data into wide table: {:?}", op_err);
-                };
+            });
+            transformation(
+                read_stream_lines(stdout, STDOUT_STREAM),
+                parser,
+                options,
+                source,
+                src_name,
+                shared_connection,
+                view_catalog,
+            );
+            if stderr_transformation.join().is_err() {
+                eprintln!("Unexpected error in stderr transformation thread");
             }
-            Err(_) => write_non_json_line(line, &mut wide_table, shared_connection.clone()),
         }
+        _ => transformation(
+            read_stream_lines(stdout, STDOUT_STREAM),
+            parser,
+            options,
+            source,
+            src_name,
+            shared_connection,
+            view_catalog,
+        ),
     }
 
     // join on the monitor thread blocks until the monitor thread recvs a signal from main thread that the transormation thread should stop
@@ -142,28 +925,147 @@ fn transformation(
     }
 }
 
+/// Reads lines from a child's output stream, skipping lines that cannot be read.
+fn read_lines(stream: impl Read) -> impl Iterator<Item = String> {
+    BufReader::new(stream)
+        .lines()
+        .filter_map(|line_res| match line_res {
+            Err(_) => {
+                eprintln!("Error reading line");
+                None
+            }
+            Ok(line) => Some(line),
+        })
+}
+
+/// Reads lines from a child's output stream, tagged with the stream's name.
+fn read_stream_lines(
+    stream: impl Read,
+    stream_name: &'static str,
+) -> impl Iterator<Item = SourceLine> {
+    read_lines(stream).map(move |line| {
+        let tags = HashMap::from([(STREAM_COL.to_string(), stream_name.to_string())]);
+        SourceLine { text: line, tags }
+    })
+}
+
+/// Forwards the lines of a child's output stream into `sender`, tagged with the stream's name.
+fn spawn_stream_reader(
+    stream: impl Read + Send + 'static,
+    stream_name: &'static str,
+    sender: Sender<SourceLine>,
+) {
+    thread::spawn(move || {
+        for line in read_stream_lines(stream, stream_name) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Flattens a JSON object according to `options` and inserts it into `table` as a single row, along with the source's `tags`.
+///
+/// The event timestamp is looked for among every key of the object, including those `options` keep out of the row.
+///
+/// The elements of arrays stored in `ArrayMode::Rows` are inserted into the child tables of `table` along with the row they belong to,
+/// none of them are kept if any of them cannot be inserted.
+pub fn write_json_object(
+    json_val: &Value,
+    tags: HashMap<String, String>,
+    options: &IngestOptions,
+    table: &mut SourceTable,
+    shared_connection: Arc<Mutex<Connection>>,
+) -> Result<(), StorageInsertionError> {
+    let tags: HashMap<String, SqlValue> = tags
+        .into_iter()
+        .map(|(col, value)| (col, SqlValue::Text(value)))
+        .collect();
+    // the event timestamp may sit in a key that is not stored
+    let all_fields = options.filters_keys().then(|| {
+        let mut all_fields = flatten_json(json_val, &options.without_key_filters()).columns;
+        all_fields.extend(tags.clone());
+        all_fields
+    });
+    let record = flatten_json(json_val, options);
+    let mut flattened_map = record.columns;
+    shelve_shadowed_fields(&mut flattened_map, tags.keys().map(String::as_str));
+    flattened_map.extend(tags);
+    let children = record
+        .children
+        .into_iter()
+        .map(|child| {
+            let mut row = child.columns;
+            row.insert(
+                ARRAY_INDEX_COL.to_string(),
+                SqlValue::Integer(child.index as i64),
+            );
+            (sanitize_table_name(&child.path), row)
+        })
+        .collect();
+    table.insert(
+        flattened_map,
+        children,
+        all_fields.as_ref(),
+        shared_connection,
+    )?;
+    Ok(())
+}
+
+/// Moves the fields of `row` named like one of `cols`, compared case-insensitively the way SQLite compares columns,
+/// to `<field>__record` so the value the ingestion sets for the column does not overwrite them.
+fn shelve_shadowed_fields<'a>(
+    row: &mut HashMap<String, SqlValue>,
+    cols: impl Iterator<Item = &'a str> + Clone,
+) {
+    let shadowed: Vec<String> = row
+        .keys()
+        .filter(|key| cols.clone().any(|col| key.eq_ignore_ascii_case(col)))
+        .cloned()
+        .collect();
+    for key in shadowed {
+        let value = row.remove(&key).expect("key was just found");
+        let mut shelved = format!("{key}{SHADOWED_FIELD_SUFFIX}");
+        while row.contains_key(&shelved) {
+            shelved.push_str(SHADOWED_FIELD_SUFFIX);
+        }
+        row.insert(shelved, value);
+    }
+}
+
 // convert line that could not be parsed correctly to json and add it as a catch all
 fn write_non_json_line(
-    line: String,
-    wide_table: &mut EvolvingWideTable,
+    line: SourceLine,
+    table: &mut SourceTable,
     shared_connection: Arc<Mutex<Connection>>,
 ) {
-    let mut err_fmt = HashMap::<String, String>::new();
-    err_fmt.insert(RAW_UNPARSABLE_COL.to_string(), line);
-    if let Err(op_err) = wide_table.insert_data(Arc::clone(&shared_connection), err_fmt) {
-        eprintln!("Error inserting data into wide table: {:?}", op_err);
+    let mut err_fmt: HashMap<String, SqlValue> = line
+        .tags
+        .into_iter()
+        .map(|(col, value)| (col, SqlValue::Text(value)))
+        .collect();
+    err_fmt.insert(RAW_UNPARSABLE_COL.to_string(), SqlValue::Text(line.text));
+    match table.insert(err_fmt, Vec::new(), None, Arc::clone(&shared_connection)) {
+        Ok(_) => table.unparsable_lines += 1,
+        Err(op_err) => eprintln!("Error inserting data into wide table: {}", op_err),
     };
 }
 
 /// Pipes a new source into the ingestion pipeline.
 ///
 /// This function is responsible for ingesting data from a new source. The source is a shell command, and the function redirects the standard output of the command to the ingestion pipeline.
+/// Standard error is captured as well according to `stderr_mode`.
 ///
 /// The function spawns a new thread to handle the ingestion of the source and does not wait for the thread to join. It increments a counter in the shared state when the thread is created and the `transformation` function is responsible for decrementing the counter when it finishes. This is used to determine when all threads have finished.
 ///
-/// The function takes three arguments:
+/// The function takes eight arguments:
 /// * `cmd`: a string that specifies the shell command to run to produce the data to ingest.
+/// * `alias`: the name the user gave the source, which its table is named after instead of the command.
+/// * `parser`: how the lines are parsed, see `SourceParser`.
+/// * `stderr_mode`: whether standard error is merged into the same table, written to a sibling `<table>_stderr` table, or inherited.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
 /// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
 /// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
 ///
 /// The function returns a `Result<(), Box<dyn Error>>`. If the function is successful, it returns `Ok(())`. If an error occurs, it returns `Err(error)`.
@@ -172,37 +1074,65 @@ fn write_non_json_line(
 ///
 /// ```
 /// let shared_connection = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+/// let view_catalog = Arc::new(Mutex::new(ViewCatalog::default()));
 /// let signal = Arc::new(SharedState::new());
-/// add_src("ls -l", shared_connection, signal).unwrap();
+/// add_src("ls -l", Some("listing"), SourceParser::new(LineFormat::Auto), StderrMode::Merge, IngestOptions::default(), shared_connection, view_catalog, signal).unwrap();
 /// ```
+#[allow(clippy::too_many_arguments)]
 pub fn add_src(
     cmd: &str,
+    alias: Option<&str>,
+    parser: SourceParser,
+    stderr_mode: StderrMode,
+    options: IngestOptions,
     shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
     signal: Arc<SharedState>,
 ) -> Result<(), Box<dyn Error>> {
-    let command = Command::new("sh")
+    let stderr = match stderr_mode {
+        StderrMode::Inherit => Stdio::inherit(),
+        StderrMode::Merge | StderrMode::Table => Stdio::piped(),
+    };
+    let mut source = SourceMeta::new(None);
+    let table_src_name = source.register(
+        cmd,
+        alias,
+        &sanitize_table_name(cmd),
+        Arc::clone(&shared_connection),
+    )?;
+
+    let spawned = Command::new("sh")
         .arg("-c")
         .arg(cmd)
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .spawn()?;
-
-    // sanitize name for table creation
-    let table_src_name = cmd
-        .replace(" ", "_")
-        .replace(".", "_")
-        .replace("-", "_")
-        .replace("/", "_")
-        .replace("\\", "_")
-        .replace("~", "HOME");
+        .stderr(stderr)
+        .spawn();
+    let command = match spawned {
+        Ok(command) => command,
+        Err(e) => {
+            // the source was registered to claim its table, record that it ended without running
+            if let Err(e) = record_source_end(Arc::clone(&shared_connection), &source.id, 0, 0) {
+                eprintln!("Failed to record the end of source {}: {}", source.id, e);
+            }
+            return Err(e.into());
+        }
+    };
+    source.pid = Some(command.id());
 
     signal.incr();
 
     thread::spawn({
         move || {
-            transformation(
+            ingest_child(
                 command,
+                source,
                 &table_src_name,
+                parser,
+                stderr_mode,
+                options,
                 Arc::clone(&shared_connection),
+                view_catalog,
                 signal,
             )
         }
@@ -210,3 +1140,759 @@ pub fn add_src(
 
     Ok(())
 }
+
+/// Pipes a file into the ingestion pipeline, following it natively instead of through `tail -f`.
+///
+/// The file is read from the beginning, or from the offset persisted by a previous run against the same DB, and is followed across rotations until the stop signal is set. See `FileTailer` for the details.
+/// Gzip and zstd compressed files are decompressed transparently and read once instead of followed.
+/// Every row records the file it came from in the `_file` column.
+///
+/// The function takes seven arguments:
+/// * `path`: the path of the file to follow, it does not need to exist yet.
+/// * `include_rotated`: whether to first ingest the file's rotated archives (`app.log.2.gz`, `app.log.1`, ...), oldest first.
+/// * `parser`: how the lines are parsed, see `SourceParser`.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If the function is successful, it returns `Ok(())`. If an error occurs, it returns `Err(error)`.
+pub fn add_file_src(
+    path: &str,
+    include_rotated: bool,
+    parser: SourceParser,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let live_path = PathBuf::from(path);
+
+    let mut lines: Box<dyn Iterator<Item = SourceLine> + Send> = Box::new(std::iter::empty());
+    if include_rotated {
+        for archive_path in rotation_set(&live_path) {
+            let archive = ArchiveReader::new(
+                archive_path.clone(),
+                Some(live_path.clone()),
+                Arc::clone(&shared_connection),
+                Arc::clone(&signal),
+            );
+            lines = Box::new(lines.chain(tag_with_file(archive, &archive_path)));
+        }
+    }
+
+    let live = open_file_lines(
+        live_path.clone(),
+        Arc::clone(&shared_connection),
+        Arc::clone(&signal),
+    )
+    .map_err(|e| format!("failed to load persisted offset: {:?}", e))?;
+    lines = Box::new(lines.chain(tag_with_file(live, &live_path)));
+
+    let source = SourceMeta::new(None);
+    let table_src_name = source.register(
+        path,
+        None,
+        &sanitize_table_name(path),
+        Arc::clone(&shared_connection),
+    )?;
+
+    signal.incr();
+
+    thread::spawn({
+        move || {
+            transformation(
+                lines,
+                parser,
+                options,
+                source,
+                &table_src_name,
+                Arc::clone(&shared_connection),
+                view_catalog,
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+        }
+    });
+
+    Ok(())
+}
+
+fn tag_with_file(
+    lines: impl Iterator<Item = String> + Send,
+    path: &Path,
+) -> impl Iterator<Item = SourceLine> + Send {
+    let file_name = path.to_string_lossy().to_string();
+    lines.map(move |text| SourceLine {
+        text,
+        tags: HashMap::from([(FILE_COL.to_string(), file_name.clone())]),
+    })
+}
+
+/// Pipes the process's standard input into the ingestion pipeline, e.g. `kubectl logs ... | logparsely noninteractive -s -`.
+///
+/// Lines are ingested into the `stdin` table. Reaching the end of the input ends the whole session by setting the stop signal,
+/// the same way pressing 'q' does when stdin is not a source.
+///
+/// The function takes five arguments:
+/// * `parser`: how the lines are parsed, see `SourceParser`.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If the function is successful, it returns `Ok(())`. If an error occurs, it returns `Err(error)`.
+pub fn add_stdin_src(
+    parser: SourceParser,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let source = SourceMeta::new(None);
+    let table_name = source.register(
+        STDIN_TABLE,
+        None,
+        STDIN_TABLE,
+        Arc::clone(&shared_connection),
+    )?;
+    let (sender, receiver) = mpsc::channel::<SourceLine>();
+
+    // reading stdin blocks and cannot be interrupted, so it happens on a detached thread that dies with the process,
+    // while the transformation thread below stops as soon as the stop signal is set.
+    spawn_line_reader(BufReader::new(io::stdin()), sender, Arc::clone(&signal));
+
+    signal.incr();
+
+    thread::spawn({
+        move || {
+            transformation(
+                until_stopped(receiver, Arc::clone(&signal)),
+                parser,
+                options,
+                source,
+                &table_name,
+                Arc::clone(&shared_connection),
+                view_catalog,
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+
+            println!("Standard input closed");
+            signal.stop();
+        }
+    });
+
+    Ok(())
+}
+
+/// Starts syslog listeners and pipes the frames they receive into the `syslog` table.
+///
+/// Frames are parsed as RFC 3164 or RFC 5424 into columns such as `facility`, `severity`, `hostname`, `app_name` and `sd.<id>.<param>`,
+/// every row also records the sender's address in `_peer` and the transport in `_transport`.
+///
+/// The function takes six arguments:
+/// * `udp_addr`: an optional address to receive syslog datagrams on, e.g. `127.0.0.1:5514`.
+/// * `tcp_addr`: an optional address to accept syslog connections on.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If binding either address fails, it returns `Err(error)`.
+pub fn add_syslog_src(
+    udp_addr: Option<&str>,
+    tcp_addr: Option<&str>,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::channel::<SourceLine>();
+
+    // listeners are detached, the UDP one stops with the signal while TCP connections block on their sockets and die with the process
+    if let Some(addr) = udp_addr {
+        spawn_udp_listener(addr, sender.clone(), Arc::clone(&signal))?;
+    }
+    if let Some(addr) = tcp_addr {
+        spawn_tcp_listener(addr, sender.clone())?;
+    }
+    drop(sender);
+
+    let source = SourceMeta::new(None);
+    let table_name = source.register(
+        SYSLOG_TABLE,
+        None,
+        SYSLOG_TABLE,
+        Arc::clone(&shared_connection),
+    )?;
+
+    signal.incr();
+
+    thread::spawn({
+        move || {
+            transformation(
+                until_stopped(receiver, Arc::clone(&signal)),
+                SourceParser::new(LineFormat::Syslog),
+                options,
+                source,
+                &table_name,
+                Arc::clone(&shared_connection),
+                view_catalog,
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+        }
+    });
+
+    Ok(())
+}
+
+/// Starts an HTTP listener that ingests `POST /ingest/<source>` requests into the `<source>` table.
+///
+/// Bodies are either NDJSON or a JSON array of objects, every object is flattened like a JSON log line.
+/// Each response reports how many objects of the batch were accepted and rejected.
+/// The listener also serves as an OTLP/HTTP logs receiver on `POST /v1/logs`, with one table per `service.name`.
+///
+/// The function takes five arguments:
+/// * `addr`: the address to listen on, e.g. `127.0.0.1:9880`.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If binding the address fails, it returns `Err(error)`.
+pub fn add_http_src(
+    addr: &str,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(addr).map_err(|e| e.to_string())?;
+    println!("Listening for HTTP ingestion requests on {}", addr);
+
+    signal.incr();
+
+    thread::spawn({
+        move || {
+            serve(
+                server,
+                options,
+                Arc::clone(&shared_connection),
+                view_catalog,
+                Arc::clone(&signal),
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+        }
+    });
+
+    Ok(())
+}
+
+/// Follows the logs of every running container matching `filter` through the Docker Engine API, picking up new matching containers as they start.
+///
+/// Lines are ingested into the `docker` table, every row records the container's id, name, image and labels
+/// in the `_container_id`, `_container_name`, `_container_image` and `_container_label.<key>` columns, and its stream in `_stream`.
+///
+/// The function takes seven arguments:
+/// * `socket`: the path of the engine's unix socket, usually `/var/run/docker.sock`.
+/// * `filter`: the names and labels selecting the containers to follow, see `ContainerFilter`.
+/// * `parser`: how the lines are parsed, see `SourceParser`.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If the engine cannot be reached, it returns `Err(error)`.
+pub fn add_docker_src(
+    socket: &str,
+    filter: ContainerFilter,
+    parser: SourceParser,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let socket = PathBuf::from(socket.trim_start_matches("unix://"));
+    // fail early when the engine is not reachable rather than in the watcher thread
+    list_containers(&socket, &filter).map_err(|e| format!("failed to list containers: {}", e))?;
+
+    let source = SourceMeta::new(None);
+    let table_name = source.register(
+        DOCKER_TABLE,
+        None,
+        DOCKER_TABLE,
+        Arc::clone(&shared_connection),
+    )?;
+
+    let (sender, receiver) = mpsc::channel::<SourceLine>();
+
+    signal.incr();
+
+    thread::spawn({
+        let signal = Arc::clone(&signal);
+        move || watch_containers(socket, filter, sender, signal)
+    });
+
+    thread::spawn({
+        move || {
+            transformation(
+                until_stopped(receiver, Arc::clone(&signal)),
+                parser,
+                options,
+                source,
+                &table_name,
+                Arc::clone(&shared_connection),
+                view_catalog,
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+        }
+    });
+
+    Ok(())
+}
+
+/// Sends the lines of `reader` to `sender` from a new thread, until the reader ends or the stop signal is set.
+fn spawn_line_reader(
+    reader: impl BufRead + Send + 'static,
+    sender: Sender<SourceLine>,
+    signal: Arc<SharedState>,
+) {
+    thread::spawn(move || {
+        for line_res in reader.lines() {
+            let line = match line_res {
+                Err(_) => {
+                    eprintln!("Error reading line");
+                    continue;
+                }
+                Ok(line) => line,
+            };
+            if signal.is_stopped() || sender.send(SourceLine::new(line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Drains `receiver` until all its senders are dropped or the stop signal is set, whichever happens first.
+///
+/// Lines received before the stop signal was set are still returned, so they and the multi-line records they complete are not lost.
+fn until_stopped(
+    receiver: Receiver<SourceLine>,
+    signal: Arc<SharedState>,
+) -> impl Iterator<Item = SourceLine> {
+    std::iter::from_fn(move || loop {
+        if signal.is_stopped() {
+            return receiver.try_recv().ok();
+        }
+        match receiver.recv_timeout(Duration::from_millis(STOP_POLL_INTERVAL_MS)) {
+            Ok(line) => return Some(line),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    })
+}
+
+/// Pipes every file matching a glob into a single table, picking up new matching files as they appear.
+///
+/// Each matching file is followed like `add_file_src` does, and every row records the file it came from in the `_file` column.
+/// If `pattern` is a directory, every file below it is ingested.
+///
+/// The function takes six arguments:
+/// * `pattern`: a glob pattern such as `logs/**/*.json`, or a directory.
+/// * `parser`: how the lines are parsed, see `SourceParser`.
+/// * `options`: how the records are flattened into rows, see `IngestOptions`.
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `view_catalog`: the columns of the `all_logs` view, shared by every source of the session.
+/// * `signal`: a `SharedState` that can be used to signal the function to stop ingesting data.
+///
+/// The function returns a `Result<(), Box<dyn Error>>`. If the function is successful, it returns `Ok(())`. If an error occurs, it returns `Err(error)`.
+pub fn add_glob_src(
+    pattern: &str,
+    parser: SourceParser,
+    options: IngestOptions,
+    shared_connection: Arc<Mutex<Connection>>,
+    view_catalog: Arc<Mutex<ViewCatalog>>,
+    signal: Arc<SharedState>,
+) -> Result<(), Box<dyn Error>> {
+    let pattern = if Path::new(pattern).is_dir() {
+        format!("{}/**/*", pattern.trim_end_matches(['/', '\\']))
+    } else {
+        pattern.to_string()
+    };
+    // fail early on malformed patterns rather than in the watcher thread
+    glob::Pattern::new(&pattern)?;
+
+    let source = SourceMeta::new(None);
+    let table_src_name = source.register(
+        &pattern,
+        None,
+        &sanitize_table_name(&pattern),
+        Arc::clone(&shared_connection),
+    )?;
+    let (sender, receiver) = mpsc::channel::<SourceLine>();
+
+    signal.incr();
+
+    thread::spawn({
+        let shared_connection = Arc::clone(&shared_connection);
+        let signal = Arc::clone(&signal);
+        move || {
+            let sink = move |path: &Path, text: String| {
+                let tags =
+                    HashMap::from([(FILE_COL.to_string(), path.to_string_lossy().to_string())]);
+                sender.send(SourceLine { text, tags }).is_ok()
+            };
+            watch_glob(&pattern, sink, shared_connection, signal)
+        }
+    });
+
+    thread::spawn({
+        move || {
+            // the channel closes once the watcher and all of its file tailers have stopped
+            transformation(
+                receiver,
+                parser,
+                options,
+                source,
+                &table_src_name,
+                Arc::clone(&shared_connection),
+                view_catalog,
+            );
+            signal.decr_and_notify_all_children_done_awaiters();
+        }
+    });
+
+    Ok(())
+}
+
+// sanitize name for table creation, keeping letters, digits and underscores only.
+// different sources can end up with the same name, `register_source` tells their tables apart.
+pub fn sanitize_table_name(src: &str) -> String {
+    let name: String = src
+        .replace("~", "HOME")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_flatten_json_decodes_embedded_json_up_to_depth() {
+        let record = json!({
+            "log": "{\"user\":42,\"inner\":\"{\\\"deep\\\":true}\"}\n",
+            "count": "7",
+        });
+        let options = IngestOptions {
+            embedded_json_depth: 1,
+            ..IngestOptions::default()
+        };
+
+        let flattened = flatten_json(&record, &options).columns;
+        assert_eq!(flattened["log.user"], SqlValue::Integer(42));
+        assert_eq!(
+            flattened["log.inner"],
+            SqlValue::Text("{\"deep\":true}".to_string())
+        );
+        assert_eq!(flattened["count"], SqlValue::Text("7".to_string()));
+
+        let flattened = flatten_json(&record, &IngestOptions::default()).columns;
+        assert!(!flattened.contains_key("log.user"));
+    }
+
+    #[test]
+    fn test_flatten_json_filters_key_paths_and_limits_depth() {
+        let record = json!({
+            "level": "info",
+            "http": {
+                "status": 200,
+                "request": {"method": "GET", "body": {"huge": "..."}},
+                "response": {"headers": {"a": "1", "b": "2"}},
+            },
+        });
+        let glob = |pattern: &str| glob::Pattern::new(pattern).unwrap();
+        let options = IngestOptions {
+            include: vec![glob("http.*")],
+            exclude: vec![glob("http.request.body"), glob("http.response.headers.b")],
+            max_depth: Some(3),
+            ..IngestOptions::default()
+        };
+
+        assert_eq!(
+            flatten_json(&record, &options).columns,
+            HashMap::from([
+                ("http.status".to_string(), SqlValue::Integer(200)),
+                (
+                    "http.request.method".to_string(),
+                    SqlValue::Text("GET".to_string())
+                ),
+                (
+                    "http.response.headers".to_string(),
+                    SqlValue::Text("{\"a\":\"1\"}".to_string())
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_flatten_json_array_modes() {
+        let record = json!({
+            "tags": ["a", "b"],
+            "errors": [{"code": 1, "frames": [1, 2]}, "timeout"],
+            "matrix": [[1, 2]],
+        });
+        let mut options = IngestOptions::default();
+        for spec in ["json", "errors=rows", "matrix=index"] {
+            options.arrays.apply(&spec.parse().unwrap());
+        }
+
+        let flattened = flatten_json(&record, &options);
+        assert_eq!(
+            flattened.columns,
+            HashMap::from([
+                (
+                    "tags".to_string(),
+                    SqlValue::Text("[\"a\",\"b\"]".to_string())
+                ),
+                ("matrix[0][0]".to_string(), SqlValue::Integer(1)),
+                ("matrix[0][1]".to_string(), SqlValue::Integer(2)),
+            ])
+        );
+
+        let children: Vec<_> = flattened
+            .children
+            .iter()
+            .map(|child| (child.path.as_str(), child.index, child.columns.clone()))
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                (
+                    "errors",
+                    0,
+                    HashMap::from([
+                        ("code".to_string(), SqlValue::Integer(1)),
+                        ("frames".to_string(), SqlValue::Text("[1,2]".to_string())),
+                    ])
+                ),
+                (
+                    "errors",
+                    1,
+                    HashMap::from([("value".to_string(), SqlValue::Text("timeout".to_string()))])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_sql_value_keeps_integers_past_i64_exact() {
+        assert_eq!(to_sql_value(&json!(i64::MAX)), SqlValue::Integer(i64::MAX));
+        assert_eq!(
+            to_sql_value(&json!(u64::MAX)),
+            SqlValue::Text("18446744073709551615".to_string())
+        );
+        assert_eq!(to_sql_value(&json!(-1.5)), SqlValue::Real(-1.5));
+    }
+
+    #[test]
+    fn test_timestamp_is_read_from_keys_left_out_of_the_row() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let options = IngestOptions {
+            include: vec![glob::Pattern::new("msg").unwrap()],
+            ..IngestOptions::default()
+        };
+        let mut table = SourceTable::new(
+            "logs",
+            SourceMeta::new(None),
+            &options,
+            Arc::clone(&conn),
+            Default::default(),
+        )
+        .unwrap();
+        write_json_object(
+            &json!({"msg": "started", "meta": {"ts": "x"}, "time": "2024-01-02T03:04:05Z"}),
+            HashMap::new(),
+            &options,
+            &mut table,
+            Arc::clone(&conn),
+        )
+        .unwrap();
+
+        let (ts, msg): (String, String) = conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT _ts, msg FROM logs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(ts, "2024-01-02T03:04:05.000000Z");
+        assert_eq!(msg, "started");
+    }
+
+    #[test]
+    fn test_sources_sharing_a_table_number_their_rows_apart() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let options = IngestOptions::default();
+        let first = SourceMeta::new(None);
+        let second = SourceMeta::new(None);
+        for (source, msg) in [(&first, "a"), (&second, "b")] {
+            let mut table = SourceTable::new(
+                "logs",
+                source.clone(),
+                &options,
+                Arc::clone(&conn),
+                Default::default(),
+            )
+            .unwrap();
+            for _ in 0..2 {
+                write_json_object(
+                    &json!({ "msg": msg }),
+                    HashMap::new(),
+                    &options,
+                    &mut table,
+                    Arc::clone(&conn),
+                )
+                .unwrap();
+            }
+        }
+
+        let conn = conn.lock().unwrap();
+        let seqs = |source: &SourceMeta| -> Vec<i64> {
+            conn.prepare("SELECT _seq FROM logs WHERE _source_id = ?1 ORDER BY _row_id")
+                .unwrap()
+                .query_map([&source.id], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(seqs(&first), vec![1, 2]);
+        assert_eq!(seqs(&second), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_sessions_on_separate_databases_keep_their_own_view() {
+        for table_name in ["first", "second"] {
+            let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+            let options = IngestOptions::default();
+            let mut table = SourceTable::new(
+                table_name,
+                SourceMeta::new(None),
+                &options,
+                Arc::clone(&conn),
+                Default::default(),
+            )
+            .unwrap();
+            write_json_object(
+                &json!({ "msg": table_name }),
+                HashMap::new(),
+                &options,
+                &mut table,
+                Arc::clone(&conn),
+            )
+            .unwrap();
+
+            let sources: Vec<String> = conn
+                .lock()
+                .unwrap()
+                .prepare(&format!("SELECT _source FROM {ALL_LOGS_VIEW}"))
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(sources, vec![table_name.to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_record_fields_named_like_metadata_are_kept_aside() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let options = IngestOptions::default();
+        let mut table = SourceTable::new(
+            "logs",
+            SourceMeta::new(None),
+            &options,
+            Arc::clone(&conn),
+            Default::default(),
+        )
+        .unwrap();
+        write_json_object(
+            &json!({"_seq": 42, "_TS": "yesterday", "_file": "app.log", "_file__record": "x"}),
+            HashMap::from([(FILE_COL.to_string(), "/var/log/app.log".to_string())]),
+            &options,
+            &mut table,
+            Arc::clone(&conn),
+        )
+        .unwrap();
+
+        let row: (i64, i64, String, String, String, String) = conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT _seq, _seq__record, _TS__record, _file, _file__record__record, _file__record FROM logs",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                1,
+                42,
+                "yesterday".to_string(),
+                "/var/log/app.log".to_string(),
+                "app.log".to_string(),
+                "x".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_until_stopped_returns_lines_received_before_the_stop() {
+        let signal = Arc::new(SharedState::new());
+        let (sender, receiver) = mpsc::channel();
+        for text in ["a", "b"] {
+            sender.send(SourceLine::new(text.to_string())).unwrap();
+        }
+        signal.stop();
+
+        // the sender is still connected, the stop signal ends the lines once the buffered ones are read
+        let texts: Vec<String> = until_stopped(receiver, Arc::clone(&signal))
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(texts, vec!["a", "b"]);
+        drop(sender);
+    }
+
+    #[test]
+    fn test_line_reader_feeds_until_stopped_until_the_input_ends() {
+        let signal = Arc::new(SharedState::new());
+        let (sender, receiver) = mpsc::channel();
+        spawn_line_reader(
+            io::Cursor::new("{\"a\":1}\nplain\r\n\nlast"),
+            sender,
+            Arc::clone(&signal),
+        );
+
+        let texts: Vec<String> = until_stopped(receiver, signal)
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(texts, vec!["{\"a\":1}", "plain", "", "last"]);
+    }
+}
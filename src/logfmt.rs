@@ -0,0 +1,128 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides logfmt parsing functionality.
+///
+/// This module defines a `parse_logfmt` function that turns `level=info msg="started" dur=3ms` lines into JSON objects.
+use serde_json::{Map, Value};
+
+/// Parses a single logfmt line into a JSON object.
+///
+/// Values are kept as strings, quoted values may contain spaces, `=` and the escapes `\"`, `\\`, `\n`, `\r`, `\t` and `\uXXXX`.
+/// A key without a value (`debug`) is `true`, the same as Go's logfmt packages decode it, unless `allow_bare_keys` is false,
+/// in which case such a line is not considered logfmt at all. That keeps plain sentences such as `listening on port=80` out when guessing the format.
+///
+/// Returns `None` if the line is not logfmt, e.g. it has no `key=value` pair or a quoted value is never closed.
+pub fn parse_logfmt(line: &str, allow_bare_keys: bool) -> Option<Value> {
+    let mut record = Map::new();
+    let mut has_pair = false;
+    let mut chars = line.trim().chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            if c == '"' {
+                return None;
+            }
+            key.push(c);
+        }
+        if key.is_empty() {
+            return None;
+        }
+
+        if chars.next_if_eq(&'=').is_none() {
+            if !allow_bare_keys {
+                return None;
+            }
+            record.insert(key, Value::Bool(true));
+            continue;
+        }
+
+        has_pair = true;
+        let value = if chars.next_if_eq(&'"').is_some() {
+            let value = read_quoted(&mut chars)?;
+            // a closing quote has to end the value
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return None;
+            }
+            value
+        } else {
+            let mut value = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+            value
+        };
+        record.insert(key, Value::String(value));
+    }
+
+    has_pair.then_some(Value::Object(record))
+}
+
+/// Reads a quoted value up to and including its closing quote, resolving escapes.
+fn read_quoted(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                    match c {
+                        Some(c) => value.push(c),
+                        None => {
+                            value.push_str("\\u");
+                            value.push_str(&hex);
+                        }
+                    }
+                }
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                other => {
+                    value.push('\\');
+                    value.push(other);
+                }
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_logfmt_quoted_values_and_escapes() {
+        let line = r#"level=info msg="started \"api\" on a=b\n" dur=3ms empty= path=/v1?x=1 debug"#;
+        assert_eq!(
+            parse_logfmt(line, true),
+            Some(json!({
+                "level": "info",
+                "msg": "started \"api\" on a=b\n",
+                "dur": "3ms",
+                "empty": "",
+                "path": "/v1?x=1",
+                "debug": true,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_logfmt_rejects_non_logfmt() {
+        assert_eq!(parse_logfmt("listening on port=80", false), None);
+        assert_eq!(parse_logfmt("plain text line", true), None);
+        assert_eq!(parse_logfmt(r#"msg="never closed"#, true), None);
+        assert_eq!(parse_logfmt(r#"msg="a"b"#, true), None);
+        assert_eq!(parse_logfmt("", true), None);
+    }
+}
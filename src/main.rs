@@ -1,9 +1,22 @@
 // Copyright (c) Microsoft Corporation.
 
+mod access_log;
+mod archive;
 mod cli;
 mod concurrency_helper;
+mod docker;
+mod grok;
+mod http_ingest;
 mod ingestion;
+mod logfmt;
+mod multiline;
+mod otlp;
+mod prefix;
 mod storage;
+mod syslog;
+mod tailing;
+mod timestamp;
+mod unified_view;
 
 use rusqlite::Connection;
 use std::io::{self, Read};
@@ -15,8 +28,11 @@ use uuid::Uuid;
 use crate::cli::{cli_arg_parser, noninteractive_mode, CommonArgs, Mode};
 
 use crate::concurrency_helper::SharedState;
+use crate::storage::{load_all_demoted_keys, DEMOTED_KEYS_TABLE};
 
 const QUIT_POLL_INTERVAL_MS: u64 = 500;
+/// How many demoted keys of a table the end of session report names.
+const REPORTED_DEMOTED_KEYS: usize = 10;
 
 fn purge() {
     let logs_dir_path = PathBuf::from("logs"); // Relative path within a "logs" directory
@@ -77,6 +93,37 @@ fn blocking_kill_children_processes(shared_signal: Arc<SharedState>) {
     shared_signal.wait_all_children_done();
 }
 
+/// Prints the keys every table demoted to overflow storage because it ran out of columns.
+fn report_demoted_keys(shared_connection: Arc<Mutex<Connection>>) {
+    let demoted = match load_all_demoted_keys(shared_connection) {
+        Ok(demoted) => demoted,
+        Err(e) => {
+            eprintln!("Failed to load demoted keys: {}", e);
+            return;
+        }
+    };
+    for (table_name, keys) in demoted {
+        let shown: Vec<&str> = keys
+            .iter()
+            .take(REPORTED_DEMOTED_KEYS)
+            .map(String::as_str)
+            .collect();
+        let more = if keys.len() > shown.len() {
+            ", ..."
+        } else {
+            ""
+        };
+        println!(
+            "Table {} ran out of columns, {} keys were demoted to overflow storage: {}{} (see {})",
+            table_name,
+            keys.len(),
+            shown.join(", "),
+            more,
+            DEMOTED_KEYS_TABLE
+        );
+    }
+}
+
 fn main() {
     let args = cli_arg_parser();
 
@@ -97,20 +144,39 @@ fn main() {
             });
             // Db connection is shared across multiple threads in a mutable manner. So wrap it in an arc mutex.
             let shared_connection = Arc::new(Mutex::new(conn));
-            // sigkill cleanup handler
+
+            // sigint cleanup handler
+            let handler_signal = Arc::clone(&shared_signal);
+            if let Err(e) = ctrlc::set_handler(move || handler_signal.stop()) {
+                eprintln!("Failed to set Ctrl+C handler: {}", e);
+            }
+
+            let reads_stdin = args.reads_stdin();
             noninteractive_mode(
                 Arc::clone(&shared_connection),
-                args.srcs,
+                *args,
                 Arc::clone(&shared_signal),
             );
 
-            println!("Press 'q' to exit");
-            // Wait for a short duration
-            while read_key() != Some('q') {
-                std::thread::sleep(std::time::Duration::from_millis(QUIT_POLL_INTERVAL_MS));
+            if reads_stdin {
+                println!("Ingesting until standard input ends, press Ctrl+C to exit early");
+            } else {
+                println!("Press 'q' to exit");
+                let key_signal = Arc::clone(&shared_signal);
+                std::thread::spawn(move || {
+                    // Wait for a short duration
+                    while read_key() != Some('q') {
+                        std::thread::sleep(std::time::Duration::from_millis(QUIT_POLL_INTERVAL_MS));
+                    }
+                    key_signal.stop();
+                });
             }
 
+            // either 'q', Ctrl+C or the end of standard input ends the session
+            shared_signal.wait_for_stop_signal();
+
             blocking_kill_children_processes(shared_signal.clone());
+            report_demoted_keys(Arc::clone(&shared_connection));
             println!("All data has been saved to {}", db.to_str().unwrap());
         }
         Mode::Purge => {
@@ -0,0 +1,371 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides multi-line record assembly.
+///
+/// This module defines the rules that join several lines into a single record (stack traces, pretty-printed JSON),
+/// and an `assemble_records` function that applies them to the lines of a source, flushing a trailing record after a timeout.
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::docker::CONTAINER_ID_COL;
+use crate::ingestion::{SourceLine, FILE_COL, STREAM_COL};
+
+/// A record is flushed once it spans this many lines, so a missing closing brace cannot hold on to the rest of the source.
+const MAX_RECORD_LINES: usize = 10_000;
+const CONTINUATION_SPEC_PREFIX: &str = "continuation:";
+const START_SPEC_PREFIX: &str = "start:";
+const JSON_SPEC: &str = "json";
+/// The tags telling apart the streams a source interleaves (glob matches, stdout and stderr, containers), each stream is assembled on its own.
+const STREAM_IDENTITY_TAGS: &[&str] = &[FILE_COL, STREAM_COL, CONTAINER_ID_COL];
+
+/// How the lines of a source are joined into records.
+#[derive(Debug, Clone)]
+pub enum MultilineRule {
+    /// Lines matching the regex belong to the record before them, e.g. `^\s+at ` for Java stack traces.
+    Continuation(Regex),
+    /// Lines matching the regex start a new record, every other line belongs to the record before it.
+    Start(Regex),
+    /// Lines starting with `{` or `[` start a JSON document that spans lines until its brackets are balanced.
+    Json,
+}
+
+/// A multi-line rule along with how long a record may wait for more lines before it is written anyway.
+#[derive(Debug, Clone)]
+pub struct Multiline {
+    pub rule: MultilineRule,
+    pub flush_timeout: Duration,
+}
+
+impl Multiline {
+    /// Creates a multi-line rule from its CLI spelling: `continuation:<regex>`, `start:<regex>` or `json`.
+    pub fn from_spec(spec: &str, flush_timeout: Duration) -> Result<Self, String> {
+        let rule = if let Some(pattern) = spec.strip_prefix(CONTINUATION_SPEC_PREFIX) {
+            MultilineRule::Continuation(Regex::new(pattern).map_err(|e| e.to_string())?)
+        } else if let Some(pattern) = spec.strip_prefix(START_SPEC_PREFIX) {
+            MultilineRule::Start(Regex::new(pattern).map_err(|e| e.to_string())?)
+        } else if spec == JSON_SPEC {
+            MultilineRule::Json
+        } else {
+            return Err(format!(
+                "unknown multiline rule {spec}, expected continuation:<regex>, start:<regex> or json"
+            ));
+        };
+        Ok(Multiline {
+            rule,
+            flush_timeout,
+        })
+    }
+}
+
+/// Joins `lines` into records according to `multiline`, the lines of a record are joined with `\n` and carry the tags of its first line.
+///
+/// Lines are assembled separately for every stream of the source, told apart by their `_file`, `_stream` and `_container_id` tags,
+/// so a stack trace is not broken up by the lines another file or container writes meanwhile.
+/// A record that has not been completed is flushed once its stream had no line for the flush timeout, or when `lines` run out.
+/// `lines` are read on a separate thread so the timeout applies even while the source blocks waiting for input.
+pub fn assemble_records(
+    lines: impl Iterator<Item = SourceLine> + Send + 'static,
+    multiline: Multiline,
+) -> impl Iterator<Item = SourceLine> {
+    let (sender, receiver) = mpsc::channel::<SourceLine>();
+    thread::spawn(move || {
+        for line in lines {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    // the assembler of every stream with a pending record, along with when that stream last had a line
+    let mut assemblers: HashMap<Vec<Option<String>>, (Assembler, Instant)> = HashMap::new();
+    let mut ready = VecDeque::new();
+    let mut done = false;
+    std::iter::from_fn(move || loop {
+        if let Some(record) = ready.pop_front() {
+            return Some(record);
+        }
+        if done {
+            return None;
+        }
+
+        let next_flush = assemblers
+            .values()
+            .map(|(_, last_line)| *last_line + multiline.flush_timeout)
+            .min();
+        let received = match next_flush {
+            Some(next_flush) => {
+                receiver.recv_timeout(next_flush.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(line) => {
+                let (assembler, last_line) = assemblers
+                    .entry(stream_identity(&line))
+                    .or_insert_with(|| (Assembler::new(multiline.rule.clone()), Instant::now()));
+                *last_line = Instant::now();
+                ready.extend(assembler.push(line));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => done = true,
+        }
+
+        let now = Instant::now();
+        for (assembler, last_line) in assemblers.values_mut() {
+            if done || now.duration_since(*last_line) >= multiline.flush_timeout {
+                ready.extend(assembler.flush());
+            }
+        }
+        assemblers.retain(|_, (assembler, _)| assembler.has_pending());
+    })
+}
+
+/// The values of the tags telling apart the streams of a source, equal for the lines of one stream.
+fn stream_identity(line: &SourceLine) -> Vec<Option<String>> {
+    STREAM_IDENTITY_TAGS
+        .iter()
+        .map(|tag| line.tags.get(*tag).cloned())
+        .collect()
+}
+
+/// The record being assembled and, for the JSON rule, how deeply nested its end is.
+struct Assembler {
+    rule: MultilineRule,
+    pending: Option<SourceLine>,
+    pending_lines: usize,
+    json_depth: JsonDepth,
+}
+
+impl Assembler {
+    fn new(rule: MultilineRule) -> Self {
+        Assembler {
+            rule,
+            pending: None,
+            pending_lines: 0,
+            json_depth: JsonDepth::default(),
+        }
+    }
+
+    fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Takes the record being assembled.
+    fn flush(&mut self) -> Option<SourceLine> {
+        self.pending_lines = 0;
+        self.json_depth = JsonDepth::default();
+        self.pending.take()
+    }
+
+    /// Adds a line, returning the record it completed if any.
+    fn push(&mut self, line: SourceLine) -> Option<SourceLine> {
+        if self.pending_lines >= MAX_RECORD_LINES {
+            let full = self.flush();
+            self.start(line);
+            return full;
+        }
+
+        match &self.rule {
+            MultilineRule::Continuation(regex) => {
+                if self.has_pending() && regex.is_match(&line.text) {
+                    self.append(line);
+                    None
+                } else {
+                    let previous = self.flush();
+                    self.start(line);
+                    previous
+                }
+            }
+            MultilineRule::Start(regex) => {
+                if !self.has_pending() || regex.is_match(&line.text) {
+                    let previous = self.flush();
+                    self.start(line);
+                    previous
+                } else {
+                    self.append(line);
+                    None
+                }
+            }
+            MultilineRule::Json => {
+                if !self.has_pending() {
+                    let trimmed = line.text.trim_start();
+                    if !trimmed.starts_with('{') && !trimmed.starts_with('[') {
+                        return Some(line);
+                    }
+                }
+                self.json_depth.scan(&line.text);
+                if self.has_pending() {
+                    self.append(line);
+                } else {
+                    self.start(line);
+                }
+                if self.json_depth.is_closed() {
+                    self.flush()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, line: SourceLine) {
+        self.pending = Some(line);
+        self.pending_lines = 1;
+    }
+
+    fn append(&mut self, line: SourceLine) {
+        if let Some(pending) = self.pending.as_mut() {
+            pending.text.push('\n');
+            pending.text.push_str(&line.text);
+            self.pending_lines += 1;
+        }
+    }
+}
+
+/// Tracks how many brackets of a JSON document are open, ignoring those inside strings.
+#[derive(Debug, Default)]
+struct JsonDepth {
+    depth: i64,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonDepth {
+    fn scan(&mut self, text: &str) {
+        for c in text.chars() {
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.depth += 1,
+                '}' | ']' => self.depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether the document is complete, or its brackets can no longer balance.
+    fn is_closed(&self) -> bool {
+        self.depth <= 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(rule: MultilineRule, lines: &[&str]) -> Vec<String> {
+        let mut assembler = Assembler::new(rule);
+        let mut records: Vec<String> = lines
+            .iter()
+            .filter_map(|line| assembler.push(SourceLine::new(line.to_string())))
+            .map(|record| record.text)
+            .collect();
+        records.extend(assembler.flush().map(|record| record.text));
+        records
+    }
+
+    #[test]
+    fn test_continuation_and_start_rules() {
+        let trace = [
+            "INFO started",
+            "ERROR boom",
+            "java.lang.IllegalStateException: x",
+            "\tat Foo.bar(Foo.java:1)",
+            "\tat Foo.main(Foo.java:2)",
+            "INFO recovered",
+        ];
+        let expected = vec![
+            "INFO started".to_string(),
+            "ERROR boom".to_string(),
+            "java.lang.IllegalStateException: x\n\tat Foo.bar(Foo.java:1)\n\tat Foo.main(Foo.java:2)"
+                .to_string(),
+            "INFO recovered".to_string(),
+        ];
+        assert_eq!(
+            assemble(
+                MultilineRule::Continuation(Regex::new(r"^\s+at ").unwrap()),
+                &trace
+            ),
+            expected
+        );
+        assert_eq!(
+            assemble(
+                MultilineRule::Start(Regex::new(r"^(INFO|ERROR) ").unwrap()),
+                &trace
+            ),
+            vec![
+                "INFO started".to_string(),
+                "ERROR boom\njava.lang.IllegalStateException: x\n\tat Foo.bar(Foo.java:1)\n\tat Foo.main(Foo.java:2)"
+                    .to_string(),
+                "INFO recovered".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interleaved_streams_are_assembled_separately() {
+        let line = |file: &str, text: &str| {
+            let mut line = SourceLine::new(text.to_string());
+            line.tags.insert(FILE_COL.to_string(), file.to_string());
+            line
+        };
+        let lines = vec![
+            line("a.log", "ERROR boom"),
+            line("b.log", "INFO tick"),
+            line("a.log", "\tat Foo.bar(Foo.java:1)"),
+            line("b.log", "INFO tock"),
+            line("a.log", "\tat Foo.main(Foo.java:2)"),
+        ];
+        let multiline = Multiline {
+            rule: MultilineRule::Continuation(Regex::new(r"^\s+at ").unwrap()),
+            flush_timeout: Duration::from_secs(60),
+        };
+        let mut records: Vec<(String, String)> = assemble_records(lines.into_iter(), multiline)
+            .map(|record| (record.tags[FILE_COL].clone(), record.text))
+            .collect();
+        records.sort();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    "a.log".to_string(),
+                    "ERROR boom\n\tat Foo.bar(Foo.java:1)\n\tat Foo.main(Foo.java:2)".to_string()
+                ),
+                ("b.log".to_string(), "INFO tick".to_string()),
+                ("b.log".to_string(), "INFO tock".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_rule_reads_objects_across_lines() {
+        let lines = [
+            "{\"single\": 1}",
+            "{",
+            "  \"msg\": \"a } in a string\",",
+            "  \"nested\": {\"b\": [1, 2]}",
+            "}",
+            "plain text",
+        ];
+        assert_eq!(
+            assemble(MultilineRule::Json, &lines),
+            vec![
+                "{\"single\": 1}".to_string(),
+                "{\n  \"msg\": \"a } in a string\",\n  \"nested\": {\"b\": [1, 2]}\n}".to_string(),
+                "plain text".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,656 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides decoding of OpenTelemetry OTLP/HTTP log export requests.
+///
+/// This module decodes `ExportLogsServiceRequest` bodies in both the JSON and the protobuf encoding,
+/// and maps every log record onto a JSON object with well-known keys that flattens into columns such as
+/// `resource.service.name`, `scope.name`, `severity_text`, `body`, `trace_id` and `span_id`.
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use prost::Message;
+use serde_json::{json, Map, Value};
+
+/// The path OTLP/HTTP exporters post logs to.
+pub const LOGS_ROUTE: &str = "/v1/logs";
+
+/// The encoding of an OTLP/HTTP request, responses use the same encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Protobuf,
+}
+
+impl Encoding {
+    /// Picks the encoding from a `Content-Type` header, `None` if it is not one OTLP/HTTP defines.
+    pub fn from_content_type(content_type: &str) -> Option<Encoding> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        match mime {
+            "application/json" => Some(Encoding::Json),
+            "application/x-protobuf" => Some(Encoding::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// The `Content-Type` of responses in this encoding.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Protobuf => "application/x-protobuf",
+        }
+    }
+}
+
+/// A single log record mapped onto flattenable JSON, along with the service that emitted it.
+pub struct OtlpLog {
+    /// The `service.name` resource attribute, if the resource had one.
+    pub service_name: Option<String>,
+    /// The mapped log record.
+    pub record: Value,
+}
+
+/// Decodes an export request body into the OTLP/JSON representation.
+///
+/// Protobuf bodies are converted to OTLP/JSON so both encodings share the same mapping in `log_records`.
+pub fn decode_request(body: &[u8], encoding: Encoding) -> Result<Value, String> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+        Encoding::Protobuf => ExportLogsServiceRequest::decode(body)
+            .map(|request| request_to_json(&request))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Encodes an `ExportLogsServiceResponse`, reporting a partial success if some records were rejected.
+pub fn encode_response(rejected: usize, encoding: Encoding) -> Vec<u8> {
+    let error_message = format!("{rejected} log records could not be ingested");
+    match encoding {
+        Encoding::Json => {
+            let response = if rejected == 0 {
+                json!({})
+            } else {
+                json!({"partialSuccess": {"rejectedLogRecords": rejected, "errorMessage": error_message}})
+            };
+            response.to_string().into_bytes()
+        }
+        Encoding::Protobuf => {
+            let partial_success = (rejected > 0).then_some(ExportLogsPartialSuccess {
+                rejected_log_records: rejected as i64,
+                error_message,
+            });
+            ExportLogsServiceResponse { partial_success }.encode_to_vec()
+        }
+    }
+}
+
+/// Maps every log record of an OTLP/JSON export request onto a JSON object.
+///
+/// Resource attributes are nested under `resource`, the instrumentation scope under `scope` and record attributes under
+/// `attributes`. Ids are hex encoded, and `body` keeps its structure so structured bodies flatten into `body.<key>` columns.
+pub fn log_records(request: &Value) -> Vec<OtlpLog> {
+    let mut logs = Vec::new();
+
+    for resource_logs in array(request, "resourceLogs") {
+        let resource = key_values(array(&resource_logs["resource"], "attributes"));
+        let service_name = resource
+            .get("service.name")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        for scope_logs in array(resource_logs, "scopeLogs") {
+            let scope_json = &scope_logs["scope"];
+            let mut scope = Map::new();
+            insert_non_empty(&mut scope, "name", scope_json["name"].clone());
+            insert_non_empty(&mut scope, "version", scope_json["version"].clone());
+            let scope_attributes = key_values(array(scope_json, "attributes"));
+            if !scope_attributes.is_empty() {
+                scope.insert("attributes".to_string(), Value::Object(scope_attributes));
+            }
+
+            for log_record in array(scope_logs, "logRecords") {
+                let mut record = Map::new();
+                for (field, column) in [
+                    ("timeUnixNano", "time_unix_nano"),
+                    ("observedTimeUnixNano", "observed_time_unix_nano"),
+                ] {
+                    if let Some(nanos) = integer(&log_record[field]).filter(|nanos| *nanos != 0) {
+                        record.insert(column.to_string(), Value::from(nanos));
+                    }
+                }
+                if let Some(number) = integer(&log_record["severityNumber"]).filter(|n| *n != 0) {
+                    record.insert("severity_number".to_string(), Value::from(number));
+                }
+                insert_non_empty(
+                    &mut record,
+                    "severity_text",
+                    log_record["severityText"].clone(),
+                );
+                insert_non_empty(&mut record, "body", any_value(&log_record["body"]));
+                insert_non_empty(&mut record, "trace_id", log_record["traceId"].clone());
+                insert_non_empty(&mut record, "span_id", log_record["spanId"].clone());
+                insert_non_empty(&mut record, "event_name", log_record["eventName"].clone());
+                if let Some(flags) = integer(&log_record["flags"]).filter(|flags| *flags != 0) {
+                    record.insert("flags".to_string(), Value::from(flags));
+                }
+
+                let attributes = key_values(array(log_record, "attributes"));
+                if !attributes.is_empty() {
+                    record.insert("attributes".to_string(), Value::Object(attributes));
+                }
+                if !resource.is_empty() {
+                    record.insert("resource".to_string(), Value::Object(resource.clone()));
+                }
+                if !scope.is_empty() {
+                    record.insert("scope".to_string(), Value::Object(scope.clone()));
+                }
+
+                logs.push(OtlpLog {
+                    service_name: service_name.clone(),
+                    record: Value::Object(record),
+                });
+            }
+        }
+    }
+
+    logs
+}
+
+fn array<'a>(value: &'a Value, field: &str) -> impl Iterator<Item = &'a Value> {
+    value[field].as_array().into_iter().flatten()
+}
+
+// OTLP/JSON encodes 64 bit integers as strings, but plain numbers are accepted too.
+// unsigned values past `i64::MAX` (nanos after 2262) are dropped rather than wrapped around to negative ones
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse::<i64>().ok(),
+        _ => None,
+    }
+}
+
+fn insert_non_empty(map: &mut Map<String, Value>, key: &str, value: Value) {
+    match &value {
+        Value::Null => {}
+        Value::String(s) if s.is_empty() => {}
+        _ => {
+            map.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn key_values<'a>(pairs: impl Iterator<Item = &'a Value>) -> Map<String, Value> {
+    let mut map = Map::new();
+    for pair in pairs {
+        if let Some(key) = pair["key"].as_str() {
+            map.insert(key.to_string(), any_value(&pair["value"]));
+        }
+    }
+    map
+}
+
+/// Unwraps an OTLP/JSON `AnyValue` into a plain JSON value.
+fn any_value(value: &Value) -> Value {
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return Value::Null,
+    };
+
+    if let Some(s) = object.get("stringValue") {
+        s.clone()
+    } else if let Some(b) = object.get("boolValue") {
+        b.clone()
+    } else if let Some(i) = object.get("intValue") {
+        integer(i).map(Value::from).unwrap_or(Value::Null)
+    } else if let Some(d) = object.get("doubleValue") {
+        d.clone()
+    } else if let Some(array_value) = object.get("arrayValue") {
+        Value::Array(array(array_value, "values").map(any_value).collect())
+    } else if let Some(kvlist) = object.get("kvlistValue") {
+        Value::Object(key_values(array(kvlist, "values")))
+    } else if let Some(bytes) = object.get("bytesValue") {
+        bytes.clone()
+    } else {
+        Value::Null
+    }
+}
+
+fn request_to_json(request: &ExportLogsServiceRequest) -> Value {
+    let resource_logs: Vec<Value> = request
+        .resource_logs
+        .iter()
+        .map(|resource_logs| {
+            let resource_attributes = resource_logs
+                .resource
+                .as_ref()
+                .map(|resource| key_values_to_json(&resource.attributes))
+                .unwrap_or_default();
+            let scope_logs: Vec<Value> = resource_logs
+                .scope_logs
+                .iter()
+                .map(|scope_logs| {
+                    let scope = scope_logs.scope.as_ref().map(|scope| {
+                        json!({
+                            "name": scope.name,
+                            "version": scope.version,
+                            "attributes": key_values_to_json(&scope.attributes),
+                        })
+                    });
+                    let log_records: Vec<Value> = scope_logs
+                        .log_records
+                        .iter()
+                        .map(log_record_to_json)
+                        .collect();
+                    json!({"scope": scope, "logRecords": log_records})
+                })
+                .collect();
+            json!({
+                "resource": {"attributes": resource_attributes},
+                "scopeLogs": scope_logs,
+            })
+        })
+        .collect();
+
+    json!({ "resourceLogs": resource_logs })
+}
+
+fn log_record_to_json(log_record: &LogRecord) -> Value {
+    json!({
+        "timeUnixNano": log_record.time_unix_nano.to_string(),
+        "observedTimeUnixNano": log_record.observed_time_unix_nano.to_string(),
+        "severityNumber": log_record.severity_number,
+        "severityText": log_record.severity_text,
+        "body": log_record.body.as_ref().map(any_value_to_json),
+        "attributes": key_values_to_json(&log_record.attributes),
+        "flags": log_record.flags,
+        "traceId": hex(&log_record.trace_id),
+        "spanId": hex(&log_record.span_id),
+        "eventName": log_record.event_name,
+    })
+}
+
+fn key_values_to_json(pairs: &[KeyValue]) -> Vec<Value> {
+    pairs
+        .iter()
+        .map(|pair| {
+            json!({
+                "key": pair.key,
+                "value": pair.value.as_ref().map(any_value_to_json),
+            })
+        })
+        .collect()
+}
+
+fn any_value_to_json(value: &AnyValue) -> Value {
+    match &value.value {
+        Some(any_value::Value::StringValue(s)) => json!({ "stringValue": s }),
+        Some(any_value::Value::BoolValue(b)) => json!({ "boolValue": b }),
+        Some(any_value::Value::IntValue(i)) => json!({ "intValue": i.to_string() }),
+        Some(any_value::Value::DoubleValue(d)) => json!({ "doubleValue": d }),
+        Some(any_value::Value::ArrayValue(array)) => json!({
+            "arrayValue": {"values": array.values.iter().map(any_value_to_json).collect::<Vec<_>>()}
+        }),
+        Some(any_value::Value::KvlistValue(kvlist)) => json!({
+            "kvlistValue": {"values": key_values_to_json(&kvlist.values)}
+        }),
+        Some(any_value::Value::BytesValue(bytes)) => json!({ "bytesValue": BASE64.encode(bytes) }),
+        None => json!({}),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// Messages from opentelemetry/proto/collector/logs/v1/logs_service.proto and the protos it imports,
+// limited to the fields the mapping uses. Unknown fields are skipped by the decoder.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportLogsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportLogsServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportLogsPartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportLogsPartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_log_records: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceLogs {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_logs: Vec<ScopeLogs>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ScopeLogs {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub log_records: Vec<LogRecord>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(message, repeated, tag = "3")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LogRecord {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "11")]
+    pub observed_time_unix_nano: u64,
+    #[prost(int32, tag = "2")]
+    pub severity_number: i32,
+    #[prost(string, tag = "3")]
+    pub severity_text: String,
+    #[prost(message, optional, tag = "5")]
+    pub body: Option<AnyValue>,
+    #[prost(message, repeated, tag = "6")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed32, tag = "8")]
+    pub flags: u32,
+    #[prost(bytes = "vec", tag = "9")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "10")]
+    pub span_id: Vec<u8>,
+    #[prost(string, tag = "12")]
+    pub event_name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AnyValue {
+    #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub value: Option<any_value::Value>,
+}
+
+pub mod any_value {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    #[allow(clippy::enum_variant_names)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+        #[prost(message, tag = "5")]
+        ArrayValue(super::ArrayValue),
+        #[prost(message, tag = "6")]
+        KvlistValue(super::KeyValueList),
+        #[prost(bytes, tag = "7")]
+        BytesValue(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ArrayValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<KeyValue>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::TimestampRule;
+    use rusqlite::types::Value as SqlValue;
+    use std::collections::HashMap;
+
+    fn string_value(s: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(any_value::Value::StringValue(s.to_string())),
+        })
+    }
+
+    #[test]
+    fn test_protobuf_and_json_map_to_the_same_record() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: "service.name".to_string(),
+                        value: string_value("checkout"),
+                    }],
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: "app.logger".to_string(),
+                        ..Default::default()
+                    }),
+                    log_records: vec![LogRecord {
+                        time_unix_nano: 1_700_000_000_000_000_000,
+                        severity_number: 17,
+                        severity_text: "ERROR".to_string(),
+                        body: string_value("payment failed"),
+                        attributes: vec![KeyValue {
+                            key: "order.id".to_string(),
+                            value: Some(AnyValue {
+                                value: Some(any_value::Value::IntValue(42)),
+                            }),
+                        }],
+                        trace_id: vec![0xab; 16],
+                        span_id: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+        };
+
+        let from_proto = decode_request(&request.encode_to_vec(), Encoding::Protobuf).unwrap();
+        let json_body = json!({
+            "resourceLogs": [{
+                "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "checkout"}}]},
+                "scopeLogs": [{
+                    "scope": {"name": "app.logger"},
+                    "logRecords": [{
+                        "timeUnixNano": "1700000000000000000",
+                        "severityNumber": 17,
+                        "severityText": "ERROR",
+                        "body": {"stringValue": "payment failed"},
+                        "attributes": [{"key": "order.id", "value": {"intValue": "42"}}],
+                        "traceId": "abababababababababababababababab",
+                        "spanId": "0102030405060708"
+                    }]
+                }]
+            }]
+        })
+        .to_string();
+        let from_json = decode_request(json_body.as_bytes(), Encoding::Json).unwrap();
+
+        let expected = json!({
+            "time_unix_nano": 1_700_000_000_000_000_000i64,
+            "severity_number": 17,
+            "severity_text": "ERROR",
+            "body": "payment failed",
+            "trace_id": "abababababababababababababababab",
+            "span_id": "0102030405060708",
+            "attributes": {"order.id": 42},
+            "resource": {"service.name": "checkout"},
+            "scope": {"name": "app.logger"}
+        });
+        for decoded in [from_proto, from_json] {
+            let logs = log_records(&decoded);
+            assert_eq!(logs.len(), 1);
+            assert_eq!(logs[0].service_name.as_deref(), Some("checkout"));
+            assert_eq!(logs[0].record, expected);
+        }
+    }
+
+    #[test]
+    fn test_records_are_stamped_with_their_time_or_else_their_observed_time() {
+        let request = json!({
+            "resourceLogs": [{
+                "scopeLogs": [{
+                    "logRecords": [
+                        {"timeUnixNano": "1704164645123000000", "observedTimeUnixNano": "1704164646000000000"},
+                        {"timeUnixNano": "0", "observedTimeUnixNano": "1704164646000000000"},
+                        {"body": {"stringValue": "no time at all"}}
+                    ]
+                }]
+            }]
+        });
+
+        let stamps: Vec<Option<String>> = log_records(&request)
+            .iter()
+            .map(|log| {
+                let row: HashMap<String, SqlValue> = log
+                    .record
+                    .as_object()
+                    .unwrap()
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        Some((key.clone(), SqlValue::Integer(value.as_i64()?)))
+                    })
+                    .collect();
+                TimestampRule::default().extract(&row)
+            })
+            .collect();
+        assert_eq!(
+            stamps,
+            vec![
+                Some("2024-01-02T03:04:05.123000Z".to_string()),
+                Some("2024-01-02T03:04:06.000000Z".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_responses_report_partial_success_in_the_request_encoding() {
+        let json_response = |rejected| -> Value {
+            serde_json::from_slice(&encode_response(rejected, Encoding::Json)).unwrap()
+        };
+        assert_eq!(json_response(0), json!({}));
+        assert_eq!(
+            json_response(2),
+            json!({"partialSuccess": {"rejectedLogRecords": 2, "errorMessage": "2 log records could not be ingested"}})
+        );
+
+        let proto_response = |rejected| {
+            ExportLogsServiceResponse::decode(&encode_response(rejected, Encoding::Protobuf)[..])
+                .unwrap()
+                .partial_success
+        };
+        assert_eq!(proto_response(0), None);
+        assert_eq!(
+            proto_response(3),
+            Some(ExportLogsPartialSuccess {
+                rejected_log_records: 3,
+                error_message: "3 log records could not be ingested".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_structured_bodies_keep_their_structure() {
+        let log_record = LogRecord {
+            body: Some(AnyValue {
+                value: Some(any_value::Value::KvlistValue(KeyValueList {
+                    values: vec![
+                        KeyValue {
+                            key: "tags".to_string(),
+                            value: Some(AnyValue {
+                                value: Some(any_value::Value::ArrayValue(ArrayValue {
+                                    values: vec![
+                                        string_value("a").unwrap(),
+                                        AnyValue {
+                                            value: Some(any_value::Value::IntValue(-7)),
+                                        },
+                                    ],
+                                })),
+                            }),
+                        },
+                        KeyValue {
+                            key: "raw".to_string(),
+                            value: Some(AnyValue {
+                                value: Some(any_value::Value::BytesValue(vec![1, 2, 3])),
+                            }),
+                        },
+                    ],
+                })),
+            }),
+            ..Default::default()
+        };
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: None,
+                scope_logs: vec![ScopeLogs {
+                    scope: None,
+                    log_records: vec![log_record],
+                }],
+            }],
+        };
+
+        let decoded = decode_request(&request.encode_to_vec(), Encoding::Protobuf).unwrap();
+        let logs = log_records(&decoded);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].service_name, None);
+        assert_eq!(
+            logs[0].record["body"],
+            json!({"tags": ["a", -7], "raw": "AQID"})
+        );
+    }
+
+    #[test]
+    fn test_malformed_requests_and_values() {
+        assert!(decode_request(b"{\"resourceLogs\": [", Encoding::Json).is_err());
+        assert!(decode_request(&[0xff, 0xff, 0xff], Encoding::Protobuf).is_err());
+        assert!(log_records(&json!({"resourceLogs": "none"})).is_empty());
+        assert!(log_records(&json!([1, 2])).is_empty());
+
+        let request = json!({
+            "resourceLogs": [{
+                "scopeLogs": [{
+                    "logRecords": [{
+                        "timeUnixNano": "18446744073709551615",
+                        "observedTimeUnixNano": 18446744073709551615u64,
+                        "severityNumber": "high",
+                        "body": {"intValue": "12x"},
+                        "attributes": [{"value": {"stringValue": "no key"}}, {"key": "empty"}]
+                    }]
+                }]
+            }]
+        });
+        let logs = log_records(&request);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].record, json!({"attributes": {"empty": null}}));
+    }
+}
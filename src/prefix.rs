@@ -0,0 +1,142 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides line prefix extraction.
+///
+/// Tools such as `docker logs -t`, `kubectl logs --prefix` and `docker compose logs` prepend text to every line of the payload.
+/// This module defines a `PrefixRule` that strips such a prefix so the rest of the line parses normally, and turns it into `_prefix_*` columns.
+use regex::Regex;
+
+use crate::ingestion::SourceLine;
+
+/// Prefix of the columns the named captures of a prefix are stored in, e.g. `_prefix_ts`.
+pub const PREFIX_COL_PREFIX: &str = "_prefix_";
+const REGEX_SPEC_PREFIX: &str = "regex:";
+const AUTO_SPEC: &str = "auto";
+
+/// An RFC 3339 timestamp as Docker and Kubernetes print them, e.g. `2024-01-02T03:04:05.123456789Z`.
+const TIMESTAMP: &str = r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2})";
+
+/// The prefixes of the tools we commonly pipe logs from, by name.
+const PRESETS: &[(&str, &str)] = &[
+    // kubectl logs --prefix [--timestamps]: `[pod/web-7d9f/app] {...}`
+    (
+        "kubectl",
+        r"^\[pod/(?P<pod>[^/\]]+)/(?P<container>[^\]]+)\] (?:(?P<ts>{TS}) )?",
+    ),
+    // docker compose logs [-t]: `web-1  | {...}`
+    (
+        "compose",
+        r"^(?P<container>[\w.-]+)\s*\| (?:(?P<ts>{TS}) )?",
+    ),
+    // docker logs -t: `2024-01-02T03:04:05.123456789Z {...}`
+    ("docker", r"^(?P<ts>{TS}) "),
+];
+
+/// Strips a known prefix from lines, keeping its named captures as columns.
+#[derive(Debug, Clone)]
+pub struct PrefixRule {
+    /// Tried in order, the first one that matches at the start of the line wins.
+    regexes: Vec<Regex>,
+}
+
+impl PrefixRule {
+    /// Creates a rule from its CLI spelling: a preset (`docker`, `kubectl`, `compose`), `auto` to try every preset,
+    /// or `regex:<regex>` whose named captures become `_prefix_<name>` columns.
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let patterns: Vec<String> = if let Some(pattern) = spec.strip_prefix(REGEX_SPEC_PREFIX) {
+            vec![pattern.to_string()]
+        } else if spec == AUTO_SPEC {
+            PRESETS.iter().map(|(_, pattern)| preset(pattern)).collect()
+        } else {
+            let (_, pattern) = PRESETS
+                .iter()
+                .find(|(name, _)| *name == spec)
+                .ok_or_else(|| {
+                    format!("unknown prefix {spec}, expected docker, kubectl, compose, auto or regex:<regex>")
+                })?;
+            vec![preset(pattern)]
+        };
+
+        let regexes = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PrefixRule { regexes })
+    }
+
+    /// Strips the prefix of `line` and adds its captures to the line's tags, lines without the prefix are returned unchanged.
+    pub fn extract(&self, mut line: SourceLine) -> SourceLine {
+        for regex in &self.regexes {
+            let captures = match regex.captures(&line.text) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            let prefix = captures.get(0).expect("group 0 is the whole match");
+            if prefix.start() != 0 {
+                continue;
+            }
+
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    line.tags.insert(
+                        format!("{PREFIX_COL_PREFIX}{name}"),
+                        value.as_str().to_string(),
+                    );
+                }
+            }
+            let end = prefix.end();
+            line.text.drain(..end);
+            break;
+        }
+        line
+    }
+}
+
+fn preset(pattern: &str) -> String {
+    pattern.replace("{TS}", TIMESTAMP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn extract(spec: &str, text: &str) -> (String, HashMap<String, String>) {
+        let line = PrefixRule::from_spec(spec)
+            .unwrap()
+            .extract(SourceLine::new(text.to_string()));
+        (line.text, line.tags)
+    }
+
+    #[test]
+    fn test_presets() {
+        let (text, tags) = extract("auto", "2024-01-02T03:04:05.123456789Z {\"a\":1}");
+        assert_eq!(text, "{\"a\":1}");
+        assert_eq!(tags["_prefix_ts"], "2024-01-02T03:04:05.123456789Z");
+
+        let (text, tags) = extract("auto", "[pod/web-7d9f/app] {\"a\":1}");
+        assert_eq!(text, "{\"a\":1}");
+        assert_eq!(tags["_prefix_pod"], "web-7d9f");
+        assert_eq!(tags["_prefix_container"], "app");
+
+        let (text, tags) = extract("compose", "web-1  | 2024-01-02T03:04:05Z {\"a\":1}");
+        assert_eq!(text, "{\"a\":1}");
+        assert_eq!(tags["_prefix_container"], "web-1");
+        assert_eq!(tags["_prefix_ts"], "2024-01-02T03:04:05Z");
+
+        let (text, tags) = extract("docker", "{\"a\":1}");
+        assert_eq!(text, "{\"a\":1}");
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn test_custom_regex_must_match_at_start() {
+        let (text, tags) = extract(r"regex:(?P<host>\w+): ", "node1: {\"a\":1}");
+        assert_eq!(text, "{\"a\":1}");
+        assert_eq!(tags["_prefix_host"], "node1");
+
+        let (text, tags) = extract(r"regex:(?P<host>\w+): ", "{\"msg\":\"node1: x\"}");
+        assert_eq!(text, "{\"msg\":\"node1: x\"}");
+        assert!(tags.is_empty());
+    }
+}
@@ -3,8 +3,11 @@
 /// Provides storage functionality as a wrapper/abstraction around SQLITE.
 ///
 /// This module defines a `EvolvingWideTable` struct that is used to store and query logs.
+use chrono::Utc;
+use rusqlite::types::Value as SqlValue;
 use rusqlite::Connection;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::Duration;
@@ -13,8 +16,40 @@ use std::{
     error::Error,
 };
 
+use crate::timestamp::format_timestamp;
+use crate::unified_view::ALL_LOGS_VIEW;
+
 pub const RAW_UNPARSABLE_COL: &str = "raw_unparsable_line";
-const ID_COLUMN: &str = "id";
+const FILE_OFFSETS_TABLE: &str = "_file_offsets";
+/// Records which column a key is stored in when it differs from the key, see `CaseCollision`.
+const COLUMN_MAPPINGS_TABLE: &str = "_column_mappings";
+/// Records the keys of every table that were demoted to overflow storage, see `Overflow`.
+pub const DEMOTED_KEYS_TABLE: &str = "_demoted_keys";
+/// Records every source of every run: the table it writes into, its command and alias, when it ran and how many lines it produced.
+pub const SOURCES_TABLE: &str = "_sources";
+/// Separates a table name from the number that tells it apart from the table of another source with the same name, e.g. `app_log_2`.
+const TABLE_NUMBER_SEPARATOR: &str = "_";
+/// Separates a table from the column in the name of an index, e.g. `app_log_idx_ts` for `_ts`.
+const INDEX_SUFFIX: &str = "_idx";
+/// Separates a table from its child tables, e.g. `app_log__errors`.
+const CHILD_TABLE_SEPARATOR: &str = "__";
+/// Suffix of the sibling table a command's standard error is written to, e.g. `app_stderr`.
+pub const STDERR_TABLE_SUFFIX: &str = "_stderr";
+/// Column holding the id of a row, an alias of SQLite's rowid that `VACUUM` cannot renumber.
+pub const ROW_ID_COL: &str = "_row_id";
+/// Column of a child row holding the `_row_id` of the row it belongs to.
+pub const PARENT_ID_COL: &str = "_parent_id";
+/// Column `Overflow::Json` stores the demoted keys of a row in, as a JSON object.
+pub const OVERFLOW_COL: &str = "_overflow";
+/// Suffix of the child table `Overflow::Table` stores the demoted keys of a row in, e.g. `app_log___overflow`.
+/// Child tables of arrays may not take it, so an array at key path `overflow` still gets `app_log__overflow`.
+const OVERFLOW_TABLE_SUFFIX: &str = "_overflow";
+const OVERFLOW_KEY_COL: &str = "key";
+const OVERFLOW_VALUE_COL: &str = "value";
+/// SQLite refuses to add columns past `SQLITE_MAX_COLUMN`, 2000 in default builds, some room is left for indices and views over the table.
+pub const DEFAULT_MAX_COLUMNS: usize = 1900;
+/// Separates a column from the sibling columns `TypeConflict::Split` and `CaseCollision::Suffix` store values in, e.g. `latency__text` or `Level__2`.
+const TYPE_SUFFIX_SEPARATOR: &str = "__";
 
 /// Represents errors that can occur when inserting into storage.
 ///
@@ -23,16 +58,32 @@ const ID_COLUMN: &str = "id";
 ///
 /// # Variants
 ///
-/// * `LockError` - Represents an error that occurred while trying to acquire a lock.
-/// * `RecordInsertionError` - Represents an error that occurred while trying to insert a record.
-/// * `SchemaManipulationError` - Represents an error that occurred while trying to manipulate the schema.
+/// * `Lock` - Represents an error that occurred while trying to acquire a lock.
+/// * `RecordInsertion` - Represents an error that occurred while trying to insert a record.
+/// * `SchemaManipulation` - Represents an error that occurred while trying to manipulate the schema.
 #[derive(Debug)]
 pub enum StorageInsertionError {
-    LockError(String),
-    RecordInsertionError(Box<dyn Error>),
-    SchemaManipulationError(Box<dyn Error>),
+    Lock(String),
+    RecordInsertion(Box<dyn Error>),
+    SchemaManipulation(Box<dyn Error>),
 }
 
+impl fmt::Display for StorageInsertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageInsertionError::Lock(e) => write!(f, "failed to lock the database: {e}"),
+            StorageInsertionError::RecordInsertion(e) => {
+                write!(f, "failed to insert the record: {e}")
+            }
+            StorageInsertionError::SchemaManipulation(e) => {
+                write!(f, "failed to change the schema: {e}")
+            }
+        }
+    }
+}
+
+impl Error for StorageInsertionError {}
+
 /// Represents errors that can occur when instantiating an evolving wide table.
 ///
 /// This enum provides specific variants for different types of instantiation errors,
@@ -48,14 +99,236 @@ pub enum EvolvingWideTableInstantiationError {
     SqlError(Box<dyn Error>),
 }
 
-/// A wide table store that creates a sparse matrix of text fields for querying logs.
+impl fmt::Display for EvolvingWideTableInstantiationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvolvingWideTableInstantiationError::LockError(e) => {
+                write!(f, "failed to lock the database: {e}")
+            }
+            EvolvingWideTableInstantiationError::SqlError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for EvolvingWideTableInstantiationError {}
+
+/// Represents errors that can occur when reading or writing logparsely's own bookkeeping tables.
+///
+/// # Variants
+///
+/// * `LockError` - Represents an error that occurred while trying to acquire a lock.
+/// * `SqlError` - Represents an error that occurred while executing an SQL command.
+/// * `TableConflict` - Represents a source name whose table is already taken.
+#[derive(Debug)]
+pub enum MetadataError {
+    LockError(String),
+    SqlError(Box<dyn Error>),
+    TableConflict(String),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::LockError(e) => write!(f, "failed to lock the database: {e}"),
+            MetadataError::SqlError(e) => write!(f, "{e}"),
+            MetadataError::TableConflict(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for MetadataError {}
+
+/// The type affinity of a column, picked from the first value stored in it.
+///
+/// SQLite stores every value with its own type regardless of the column's affinity, the affinity only decides
+/// which conversions SQLite applies on the way in (e.g. a number stored in a `TEXT` column becomes text).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnAffinity {
+    Integer,
+    Real,
+    Text,
+    /// No declared type, values are stored as they are. Columns first seen with a `null` get it.
+    Blob,
+}
+
+impl ColumnAffinity {
+    /// The affinity a column created for `value` gets.
+    fn of_value(value: &SqlValue) -> Self {
+        match value {
+            SqlValue::Integer(_) => ColumnAffinity::Integer,
+            SqlValue::Real(_) => ColumnAffinity::Real,
+            SqlValue::Text(_) => ColumnAffinity::Text,
+            SqlValue::Null | SqlValue::Blob(_) => ColumnAffinity::Blob,
+        }
+    }
+
+    /// The affinity SQLite derives from a declared column type, see https://www.sqlite.org/datatype3.html#determination_of_column_affinity.
+    fn from_declared_type(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        if declared.contains("INT") {
+            ColumnAffinity::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|t| declared.contains(t))
+        {
+            ColumnAffinity::Text
+        } else if declared.is_empty() || declared.contains("BLOB") {
+            ColumnAffinity::Blob
+        } else {
+            ColumnAffinity::Real
+        }
+    }
+
+    fn declared_type(&self) -> &'static str {
+        match self {
+            ColumnAffinity::Integer => "INTEGER",
+            ColumnAffinity::Real => "REAL",
+            ColumnAffinity::Text => "TEXT",
+            ColumnAffinity::Blob => "",
+        }
+    }
+
+    /// Whether `value` can be stored in a column of this affinity without changing its type.
+    fn accepts(&self, value: &SqlValue) -> bool {
+        match (self, value) {
+            (_, SqlValue::Null) | (ColumnAffinity::Blob, _) => true,
+            (ColumnAffinity::Integer | ColumnAffinity::Real, value) => {
+                matches!(value, SqlValue::Integer(_) | SqlValue::Real(_))
+            }
+            (ColumnAffinity::Text, value) => matches!(value, SqlValue::Text(_)),
+        }
+    }
+
+    /// The suffix of the sibling column `TypeConflict::Split` stores values of this affinity in.
+    fn suffix(&self) -> &'static str {
+        match self {
+            ColumnAffinity::Integer => "integer",
+            ColumnAffinity::Real => "real",
+            ColumnAffinity::Text => "text",
+            ColumnAffinity::Blob => "blob",
+        }
+    }
+}
+
+/// What happens when a field's value does not match the type of the column created for it, e.g. `latency` is `12` on one line and `"n/a"` on the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TypeConflict {
+    /// The value is stored in the column anyway, SQLite keeps its type unless the column's affinity converts it
+    /// (numbers stored in a text column become text, numeric text stored in a number column becomes a number).
+    #[default]
+    Keep,
+    /// The value is stored in a sibling column named after its type, e.g. `latency__text`, so every column holds a single type.
+    Split,
+}
+
+impl FromStr for TypeConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(TypeConflict::Keep),
+            "split" => Ok(TypeConflict::Split),
+            other => Err(format!(
+                "unknown type conflict strategy {other}, expected keep or split"
+            )),
+        }
+    }
+}
+
+/// What happens when a key differs from an existing column only in case, e.g. `Level` and `level`, which SQLite treats as the same column name.
+///
+/// Either way the key is mapped to its column deterministically, and the mapping is recorded in the `_column_mappings` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseCollision {
+    /// The key is stored in the existing column, the casing seen first names the column.
+    /// If a record has both keys, the value of the key matching the column's casing is kept.
+    #[default]
+    Merge,
+    /// The key is stored in a column of its own, named with the first free numeric suffix, e.g. `Level__2`.
+    Suffix,
+}
+
+impl FromStr for CaseCollision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(CaseCollision::Merge),
+            "suffix" => Ok(CaseCollision::Suffix),
+            other => Err(format!(
+                "unknown case collision strategy {other}, expected merge or suffix"
+            )),
+        }
+    }
+}
+
+/// Where keys go once a table has used up its column budget, existing columns keep being filled either way.
+///
+/// Demoted keys are recorded in the `_demoted_keys` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Into the `_overflow` column of the row, as a JSON object queryable with SQLite's JSON functions.
+    #[default]
+    Json,
+    /// Into the `<table>___overflow` child table, one `key`/`value` row per demoted key linked to the row by `_parent_id`.
+    Table,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Overflow::Json),
+            "table" => Ok(Overflow::Table),
+            other => Err(format!(
+                "unknown overflow storage {other}, expected json or table"
+            )),
+        }
+    }
+}
+
+/// Options for how rows are laid out in the tables of every source.
+#[derive(Debug, Clone)]
+pub struct TableOptions {
+    /// What happens when a field's type changes across lines.
+    pub type_conflict: TypeConflict,
+    /// What happens when keys differ only in case.
+    pub case_collision: CaseCollision,
+    /// How many columns a table may have before new keys are demoted to overflow storage.
+    pub max_columns: usize,
+    /// Where demoted keys are stored.
+    pub overflow: Overflow,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            type_conflict: TypeConflict::default(),
+            case_collision: CaseCollision::default(),
+            max_columns: DEFAULT_MAX_COLUMNS,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// A wide table store that creates a sparse matrix of typed fields for querying logs.
 ///
 /// This struct is used to ingest logs in an efficient manner while evolving the schema of the table as new fields are discovered.
 pub struct EvolvingWideTable {
-    /// A set of column names in the table, kept in memory for quick lookup.
-    col_lookup: HashSet<String>,
+    /// The column names in the table and their affinity, kept in memory for quick lookup.
+    col_lookup: HashMap<String, ColumnAffinity>,
+    /// The column names in the table by their ASCII lowercase form, the way SQLite compares them.
+    folded_cols: HashMap<String, String>,
+    /// The columns of keys that are not stored in the column named like them, see `CaseCollision`.
+    key_mappings: HashMap<String, String>,
+    /// The keys stored in overflow storage since the table ran out of columns, see `Overflow`.
+    demoted: HashSet<String>,
     /// The name of the table in the SQLite database.
     table_name: String,
+    options: TableOptions,
+    /// The child tables array elements are stored in, by their suffix, created the first time they are needed.
+    children: HashMap<String, EvolvingWideTable>,
 }
 
 impl EvolvingWideTable {
@@ -63,106 +336,206 @@ impl EvolvingWideTable {
     ///
     /// This function creates a new table if it does not already exist and cleans up if needed.
     ///
-    /// The function takes two arguments:
+    /// The function takes three arguments:
     /// * `table_name`: a string that specifies the name of the table.
+    /// * `options`: how rows are laid out in the table, see `TableOptions`.
     /// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
     ///
     /// The function returns a `Result<EvolvingWideTable, Box<dyn Error>>`. If the function is successful, it returns `Ok(EvolvingWideTable)`. If an error occurs, it returns `Err(error)`.
     pub fn new(
         table_name: String,
+        options: TableOptions,
         shared_connection: Arc<Mutex<Connection>>,
     ) -> Result<EvolvingWideTable, EvolvingWideTableInstantiationError> {
-        // Create the table
-        let create_query = format!(
-            "CREATE TABLE IF NOT EXISTS {table_name} (
-                {RAW_UNPARSABLE_COL} TEXT
-            )"
-        );
-
         let conn = shared_connection.lock().map_err(|e| {
             EvolvingWideTableInstantiationError::LockError(format!(
                 "Failed to acquire lock on shared connection: {e}"
             ))
         })?;
+        Self::open(table_name, options, &conn)
+    }
+
+    /// Creates the table if it does not already exist and reads its schema, on a connection the caller already locked.
+    fn open(
+        table_name: String,
+        options: TableOptions,
+        conn: &Connection,
+    ) -> Result<EvolvingWideTable, EvolvingWideTableInstantiationError> {
+        // Create the table, tables created before `_row_id` was introduced keep using the implicit rowid
+        let create_query = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                {ROW_ID_COL} INTEGER PRIMARY KEY,
+                {RAW_UNPARSABLE_COL} TEXT
+            )",
+            quote_identifier(&table_name)
+        );
 
         conn.execute(&create_query, ())
             .map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
 
-        let query = format!("PRAGMA table_info({table_name})");
+        let query = format!("PRAGMA table_info({})", quote_identifier(&table_name));
         let mut stmt = conn
             .prepare(&query)
             .map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
-        // Use query_map on the Statement to retrieve column names
-        let column_names = stmt
-            .query_map([], |row| row.get(1))
+        // Use query_map on the Statement to retrieve column names and declared types
+        let columns = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })
             .map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
 
-        let mut col_lookup: HashSet<String> = HashSet::new();
-        for col in column_names {
+        let mut col_lookup: HashMap<String, ColumnAffinity> = HashMap::new();
+        let mut folded_cols: HashMap<String, String> = HashMap::new();
+        for col in columns {
             // fail instantiation if the column name is not retrievable
-            let col_str: String =
+            let (col_str, declared_type) =
                 col.map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
-            if col_str == ID_COLUMN {
-                col_lookup.insert(col_str.clone());
-            } else {
-                let col_sanitized = col_str.trim_matches('`');
-                col_lookup.insert(col_sanitized.to_string());
-            }
+            let affinity = ColumnAffinity::from_declared_type(&declared_type);
+            col_lookup.insert(col_str, affinity);
+        }
+        for col in col_lookup.keys() {
+            folded_cols.insert(col.to_ascii_lowercase(), col.clone());
         }
+        drop(stmt);
+
+        let key_mappings = load_column_mappings(conn, &table_name)
+            .map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
+        let demoted = load_demoted_keys(conn, &table_name)
+            .map_err(|e| EvolvingWideTableInstantiationError::SqlError(Box::new(e)))?;
 
         Ok(EvolvingWideTable {
             col_lookup,
+            folded_cols,
+            key_mappings,
+            demoted,
             table_name,
+            options,
+            children: HashMap::new(),
         })
     }
 
     const MAX_DB_WRITE_ATTEMPTS: u32 = 3;
 
-    /// Inserts data into the table.
+    /// Inserts data into the table, along with the rows of its child tables.
     ///
-    /// This method is responsible for inserting data into the table. It takes a map of field names to field values and inserts a new row into the table with these values. If a field does not exist in the table, it is added.
+    /// This method is responsible for inserting data into the table. It takes a map of field names to field values and inserts a new row into the table with these values.
+    /// If a field does not exist in the table, it is added with the affinity of its value. Values whose type does not match their column are handled according to `TypeConflict`,
+    /// and fields that differ from a column only in case according to `CaseCollision`. Once the table has `max_columns` columns, new fields are stored according to `Overflow`.
+    /// Every child row is then linked to the row through `_parent_id` and inserted into the child table `<table>__<suffix>`.
+    /// Either all of them are inserted or, along with the schema changes they made, none.
     ///
-    /// The method takes one argument:
-    /// * `data`: a `HashMap<String, String>` that maps field names to field values.
+    /// The method takes three arguments:
+    /// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+    /// * `data`: a `HashMap<String, SqlValue>` that maps field names to field values.
+    /// * `children`: the child rows, each with the suffix of the child table it goes into.
     ///
-    /// The method returns a `Result<(), Box<dyn Error>>`. If the method is successful, it returns `Ok(())`. If an error occurs, it returns `Err(error)`.
+    /// The method returns a `Result<i64, StorageInsertionError>`. If the method is successful, it returns `Ok(row_id)` with the `_row_id` of the inserted row. If an error occurs, it returns `Err(error)`.
     ///
     /// # Examples
     ///
     /// ```
     /// let mut data = HashMap::new();
-    /// data.insert("field1".to_string(), "value1".to_string());
-    /// data.insert("field2".to_string(), "value2".to_string());
-    /// table.insert_data(data).unwrap();
+    /// data.insert("field1".to_string(), SqlValue::Text("value1".to_string()));
+    /// data.insert("field2".to_string(), SqlValue::Integer(2));
+    /// table.insert_data(shared_connection, data, Vec::new()).unwrap();
     /// ```
     pub fn insert_data(
         &mut self,
         shared_connection: Arc<Mutex<Connection>>,
-        data: HashMap<String, String>,
-    ) -> Result<(), StorageInsertionError> {
+        data: HashMap<String, SqlValue>,
+        children: Vec<(String, HashMap<String, SqlValue>)>,
+    ) -> Result<i64, StorageInsertionError> {
+        let conn = shared_connection
+            .lock()
+            .map_err(|e| StorageInsertionError::Lock(e.to_string()))?;
+        // the lock is held until the transaction ends, so rows of other sources sharing the connection cannot end up in it
+        let transaction = conn
+            .unchecked_transaction()
+            .map_err(|e| StorageInsertionError::RecordInsertion(Box::new(e)))?;
+
+        let inserted = self.insert_row(&transaction, data).and_then(|row_id| {
+            for (suffix, mut row) in children {
+                row.insert(PARENT_ID_COL.to_string(), SqlValue::Integer(row_id));
+                self.child_table(&suffix, &transaction)?
+                    .insert_row(&transaction, row)?;
+            }
+            Ok(row_id)
+        });
+        match inserted {
+            Ok(row_id) => {
+                transaction
+                    .commit()
+                    .map_err(|e| StorageInsertionError::RecordInsertion(Box::new(e)))?;
+                Ok(row_id)
+            }
+            Err(e) => {
+                drop(transaction);
+                self.reload(&conn);
+                Err(e)
+            }
+        }
+    }
+
+    /// Rereads the schema after a rolled back insert, so columns and child tables it added are forgotten.
+    fn reload(&mut self, conn: &Connection) {
+        match Self::open(self.table_name.clone(), self.options.clone(), conn) {
+            Ok(table) => *self = table,
+            Err(e) => eprintln!("Failed to reload the schema of {}: {e}", self.table_name),
+        }
+    }
+
+    /// Inserts a row on a connection the caller already locked, within its transaction, see `insert_data`.
+    fn insert_row(
+        &mut self,
+        conn: &Connection,
+        data: HashMap<String, SqlValue>,
+    ) -> Result<i64, StorageInsertionError> {
+        let (mut data, new_mappings) = self.resolve_columns(data);
+        let (overflow, new_demotions) = self.demote_overflowing(&mut data);
+        if !new_demotions.is_empty() && self.demoted.len() == new_demotions.len() {
+            println!(
+                "Table {} reached its budget of {} columns, new keys are stored in overflow storage (see {DEMOTED_KEYS_TABLE})",
+                self.table_name, self.options.max_columns
+            );
+        }
+        let overflow = match self.options.overflow {
+            Overflow::Json if !overflow.is_empty() => {
+                let object: serde_json::Map<String, serde_json::Value> = overflow
+                    .into_iter()
+                    .map(|(key, value)| (key, to_json_value(value)))
+                    .collect();
+                data.insert(
+                    OVERFLOW_COL.to_string(),
+                    SqlValue::Text(serde_json::Value::Object(object).to_string()),
+                );
+                Vec::new()
+            }
+            _ => overflow,
+        };
+
         // check if schema needs to be altered
         let mut new_cols = Vec::new();
-        for key in data.keys() {
-            if !self.col_lookup.contains(&key.clone()) {
-                new_cols.push(key.clone());
-                self.col_lookup.insert(key.clone());
+        for (key, value) in data.iter() {
+            if !self.col_lookup.contains_key(key) {
+                let affinity = ColumnAffinity::of_value(value);
+                new_cols.push((key.clone(), affinity));
+                self.col_lookup.insert(key.clone(), affinity);
             }
         }
 
         // dirty schema altering hacky af!
         let mut alters: Vec<String> = Vec::new();
-        for col in new_cols {
+        for (col, affinity) in new_cols {
             alters.push(format!(
-                "ALTER TABLE {} ADD `{}` TEXT; ",
-                self.table_name, col
+                "ALTER TABLE {} ADD {} {}; ",
+                quote_identifier(&self.table_name),
+                quote_identifier(&col),
+                affinity.declared_type()
             ));
         }
 
-        let batch_stmt = format!("BEGIN; {} COMMIT;", alters.join(""));
-
-        let conn = shared_connection
-            .lock()
-            .map_err(|e| StorageInsertionError::LockError(e.to_string()))?;
+        // the caller's transaction makes the changes atomic
+        let batch_stmt = alters.join("");
 
         let schema_alter_closure = || conn.execute_batch(&batch_stmt).map_err(|e| e.into());
 
@@ -171,16 +544,25 @@ impl EvolvingWideTable {
             Duration::from_secs(1),
             schema_alter_closure,
         )
-        .map_err(|e| StorageInsertionError::SchemaManipulationError(e))?;
+        .map_err(StorageInsertionError::SchemaManipulation)?;
+
+        for (key, col) in new_mappings {
+            save_column_mapping(conn, &self.table_name, &key, &col)
+                .map_err(|e| StorageInsertionError::SchemaManipulation(Box::new(e)))?;
+        }
+        for key in &new_demotions {
+            save_demoted_key(conn, &self.table_name, key)
+                .map_err(|e| StorageInsertionError::SchemaManipulation(Box::new(e)))?;
+        }
 
         // insert actual data hacky!
         let mut cols: Vec<String> = Vec::new();
         let mut vals: Vec<String> = Vec::new();
-        let mut sqlite_vals: Vec<&String> = Vec::new();
+        let mut sqlite_vals: Vec<&SqlValue> = Vec::new();
 
-        for col in self.col_lookup.iter() {
+        for col in self.col_lookup.keys() {
             if data.contains_key(col) {
-                cols.push(format!("`{}`", col));
+                cols.push(quote_identifier(col));
                 vals.push("?".to_string());
                 sqlite_vals.push(&data[col]);
             }
@@ -191,7 +573,9 @@ impl EvolvingWideTable {
 
         let insert_stmt = format!(
             "INSERT INTO {} ({}) VALUES ({});",
-            self.table_name, joined_cols, joined_vals
+            quote_identifier(&self.table_name),
+            joined_cols,
+            joined_vals
         );
 
         let insertion_closure = || {
@@ -211,11 +595,222 @@ impl EvolvingWideTable {
             Duration::from_secs(1),
             insertion_closure,
         )
-        .map_err(|e| StorageInsertionError::RecordInsertionError(e))?;
+        .map_err(StorageInsertionError::RecordInsertion)?;
+
+        let row_id = conn.last_insert_rowid();
+
+        // only demoted keys of `Overflow::Table` are left, the JSON object went into the row
+        for (key, value) in overflow {
+            let overflow_row = HashMap::from([
+                (PARENT_ID_COL.to_string(), SqlValue::Integer(row_id)),
+                (OVERFLOW_KEY_COL.to_string(), SqlValue::Text(key)),
+                (OVERFLOW_VALUE_COL.to_string(), value),
+            ]);
+            // the overflow table has a handful of columns, it must not run out of them and overflow in turn
+            let options = TableOptions {
+                max_columns: DEFAULT_MAX_COLUMNS,
+                ..self.options.clone()
+            };
+            self.open_child_table(OVERFLOW_TABLE_SUFFIX, options, conn)?
+                .insert_row(conn, overflow_row)?;
+        }
+
+        Ok(row_id)
+    }
 
+    /// Takes the fields that would need a column past the column budget out of `data`, returning them and the keys demoted for the first time.
+    ///
+    /// One column of the budget is kept free for the overflow column. Keys demoted once stay demoted, so a key never moves back and forth.
+    fn demote_overflowing(
+        &mut self,
+        data: &mut HashMap<String, SqlValue>,
+    ) -> (Vec<(String, SqlValue)>, Vec<String>) {
+        let mut new_cols: Vec<String> = data
+            .keys()
+            .filter(|col| !self.col_lookup.contains_key(*col))
+            .cloned()
+            .collect();
+        new_cols.sort();
+
+        let budget = self.options.max_columns.saturating_sub(1);
+        let mut col_count = self.col_lookup.len();
+        let mut overflow = Vec::new();
+        let mut new_demotions = Vec::new();
+        for col in new_cols {
+            if !self.demoted.contains(&col) && col_count < budget {
+                col_count += 1;
+                continue;
+            }
+            if self.demoted.insert(col.clone()) {
+                new_demotions.push(col.clone());
+            }
+            let value = data.remove(&col).expect("new columns are keys of data");
+            overflow.push((col, value));
+        }
+        (overflow, new_demotions)
+    }
+
+    /// Indexes `col`, adding it with `affinity` first if the table does not have it yet.
+    pub fn create_index(
+        &mut self,
+        col: &str,
+        affinity: ColumnAffinity,
+        shared_connection: Arc<Mutex<Connection>>,
+    ) -> Result<(), StorageInsertionError> {
+        let conn = shared_connection
+            .lock()
+            .map_err(|e| StorageInsertionError::Lock(e.to_string()))?;
+
+        if !self.col_lookup.contains_key(col) {
+            let alter = format!(
+                "ALTER TABLE {} ADD {} {}",
+                quote_identifier(&self.table_name),
+                quote_identifier(col),
+                affinity.declared_type()
+            );
+            conn.execute(&alter, ())
+                .map_err(|e| StorageInsertionError::SchemaManipulation(Box::new(e)))?;
+            self.col_lookup.insert(col.to_string(), affinity);
+            self.folded_cols
+                .insert(col.to_ascii_lowercase(), col.to_string());
+        }
+
+        let index = format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            quote_identifier(&format!("{}{INDEX_SUFFIX}{col}", self.table_name)),
+            quote_identifier(&self.table_name),
+            quote_identifier(col)
+        );
+        conn.execute(&index, ())
+            .map_err(|e| StorageInsertionError::SchemaManipulation(Box::new(e)))?;
         Ok(())
     }
 
+    /// Returns how many columns the table has, which grows whenever a field needs a new column.
+    pub fn column_count(&self) -> usize {
+        self.col_lookup.len()
+    }
+
+    /// Returns the columns of the table, in no particular order.
+    pub fn columns(&self) -> Vec<String> {
+        self.col_lookup.keys().cloned().collect()
+    }
+
+    /// Returns the name of the table in the SQLite database.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Returns the child table `<table>__<suffix>`, creating it the first time it is needed.
+    ///
+    /// Child tables hold rows that belong to a row of this table, such as the elements of an array, and share its options.
+    /// The suffix of the overflow table is reserved, compared case-insensitively the way SQLite compares table names.
+    fn child_table(
+        &mut self,
+        suffix: &str,
+        conn: &Connection,
+    ) -> Result<&mut EvolvingWideTable, StorageInsertionError> {
+        if suffix.eq_ignore_ascii_case(OVERFLOW_TABLE_SUFFIX) {
+            return Err(StorageInsertionError::SchemaManipulation(
+                format!(
+                    "Child table suffix {suffix} is reserved for the overflow table of {}",
+                    self.table_name
+                )
+                .into(),
+            ));
+        }
+        let options = self.options.clone();
+        self.open_child_table(suffix, options, conn)
+    }
+
+    fn open_child_table(
+        &mut self,
+        suffix: &str,
+        options: TableOptions,
+        conn: &Connection,
+    ) -> Result<&mut EvolvingWideTable, StorageInsertionError> {
+        if !self.children.contains_key(suffix) {
+            let table_name = format!("{}{CHILD_TABLE_SEPARATOR}{suffix}", self.table_name);
+            let child = EvolvingWideTable::open(table_name, options, conn).map_err(|e| {
+                StorageInsertionError::SchemaManipulation(
+                    format!("Failed to create child table: {e}").into(),
+                )
+            })?;
+            self.children.insert(suffix.to_string(), child);
+        }
+        Ok(self
+            .children
+            .get_mut(suffix)
+            .expect("child table was just inserted"))
+    }
+
+    /// Maps the fields of `data` to the columns they are stored in, returning the data by column and the key mappings created along the way.
+    ///
+    /// Fields are visited in key order so that collisions within a record are resolved the same way every time.
+    fn resolve_columns(
+        &mut self,
+        data: HashMap<String, SqlValue>,
+    ) -> (HashMap<String, SqlValue>, Vec<(String, String)>) {
+        let mut fields: Vec<(String, SqlValue)> = data.into_iter().collect();
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut new_mappings = Vec::new();
+        let mut resolved: HashMap<String, SqlValue> = HashMap::new();
+        for (key, value) in fields {
+            let mut col = self.column_for(&key, &mut new_mappings);
+            if self.options.type_conflict == TypeConflict::Split {
+                if let Some(affinity) = self.col_lookup.get(&col) {
+                    if !affinity.accepts(&value) {
+                        let suffix = ColumnAffinity::of_value(&value).suffix();
+                        col = self.column_for(
+                            &format!("{col}{TYPE_SUFFIX_SEPARATOR}{suffix}"),
+                            &mut new_mappings,
+                        );
+                    }
+                }
+            }
+            // a key merged into a column of another casing does not overwrite the key of the column's own casing
+            if col == key || !resolved.contains_key(&col) {
+                resolved.insert(col, value);
+            }
+        }
+        (resolved, new_mappings)
+    }
+
+    /// Returns the column `key` is stored in, reserving a name for it if it needs a new column.
+    fn column_for(&mut self, key: &str, new_mappings: &mut Vec<(String, String)>) -> String {
+        if let Some(col) = self.key_mappings.get(key) {
+            return col.clone();
+        }
+        let folded = key.to_ascii_lowercase();
+        // a field named like the row id column is kept apart from it, as if it collided in case
+        let is_row_id = folded == ROW_ID_COL;
+        let existing = match self.folded_cols.get(&folded) {
+            Some(existing) if existing == key && !is_row_id => return key.to_string(),
+            Some(existing) => existing.clone(),
+            None => {
+                self.folded_cols.insert(folded, key.to_string());
+                return key.to_string();
+            }
+        };
+
+        let col = match self.options.case_collision {
+            CaseCollision::Merge if !is_row_id => existing,
+            CaseCollision::Merge | CaseCollision::Suffix => {
+                let col = (2..)
+                    .map(|n| format!("{key}{TYPE_SUFFIX_SEPARATOR}{n}"))
+                    .find(|col| !self.folded_cols.contains_key(&col.to_ascii_lowercase()))
+                    .expect("there is always a free suffix");
+                self.folded_cols
+                    .insert(col.to_ascii_lowercase(), col.clone());
+                col
+            }
+        };
+        self.key_mappings.insert(key.to_string(), col.clone());
+        new_mappings.push((key.to_string(), col.clone()));
+        col
+    }
+
     fn attempt_with_retry<T>(
         &self,
         max_attempts: u32,
@@ -236,3 +831,873 @@ impl EvolvingWideTable {
         result
     }
 }
+
+/// The last known read position of a tailed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOffset {
+    /// Identity of the file the offset belongs to (inode on unix), used to detect rotation between runs.
+    pub file_id: Option<u64>,
+    /// Byte offset just past the last fully ingested line.
+    pub offset: u64,
+}
+
+/// Loads the persisted offset for `path`, if a previous run against the same DB recorded one.
+pub fn load_file_offset(
+    shared_connection: Arc<Mutex<Connection>>,
+    path: &str,
+) -> Result<Option<FileOffset>, MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+
+    create_file_offsets_table(&conn)?;
+
+    let query = format!("SELECT file_id, offset FROM {FILE_OFFSETS_TABLE} WHERE path = ?1");
+    let res = conn.query_row(&query, [path], |row| {
+        let file_id: Option<i64> = row.get(0)?;
+        let offset: i64 = row.get(1)?;
+        Ok(FileOffset {
+            file_id: file_id.map(|id| id as u64),
+            offset: offset as u64,
+        })
+    });
+
+    match res {
+        Ok(offset) => Ok(Some(offset)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(MetadataError::SqlError(Box::new(e))),
+    }
+}
+
+/// Persists the offset for `path`, overwriting whatever was recorded before.
+pub fn save_file_offset(
+    shared_connection: Arc<Mutex<Connection>>,
+    path: &str,
+    offset: FileOffset,
+) -> Result<(), MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+
+    create_file_offsets_table(&conn)?;
+
+    let query = format!(
+        "INSERT OR REPLACE INTO {FILE_OFFSETS_TABLE} (path, file_id, offset) VALUES (?1, ?2, ?3)"
+    );
+    conn.execute(
+        &query,
+        rusqlite::params![
+            path,
+            offset.file_id.map(|id| id as i64),
+            offset.offset as i64
+        ],
+    )
+    .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+
+    Ok(())
+}
+
+fn create_file_offsets_table(conn: &Connection) -> Result<(), MetadataError> {
+    let create_query = format!(
+        "CREATE TABLE IF NOT EXISTS {FILE_OFFSETS_TABLE} (
+            path TEXT PRIMARY KEY,
+            file_id INTEGER,
+            offset INTEGER NOT NULL
+        )"
+    );
+    conn.execute(&create_query, ())
+        .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+    Ok(())
+}
+
+/// Quotes `name` as an SQL identifier, so table and column names can hold any character, quotes included.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Records a new source in the `_sources` registry and returns the table it writes into.
+///
+/// A source named by the user writes into the table named like its alias, which keeps following the alias across runs even if it is given another command.
+/// The alias is rejected if its table is taken (see below) by another source.
+///
+/// Other sources write into the same table as the last source with the same command, so runs against the same DB add up.
+/// New ones get a table named `preferred_table`, numbered (`app_log_2`, ...) if the name is taken: another source writes into a table of that name,
+/// it exists in the DB, it is one of logparsely's own tables, or it is the `_stderr` sibling of a source table or the other way round,
+/// or there are tables named like its child tables. Table names are compared case-insensitively, the way SQLite compares them.
+///
+/// The function takes five arguments:
+/// * `shared_connection`: a thread-safe `Arc<Mutex<Connection>>` to a SQLite database.
+/// * `source_id`: the id of the source, which is recorded on every row it writes.
+/// * `command`: what the source reads, the shell command, path or pattern given on the command line.
+/// * `alias`: the name the user gave the source, if any.
+/// * `preferred_table`: the name the table gets if the source is new and has no alias, already safe to use as a table name.
+///
+/// The function returns a `Result<String, MetadataError>` with the name of the table.
+pub fn register_source(
+    shared_connection: Arc<Mutex<Connection>>,
+    source_id: &str,
+    command: &str,
+    alias: Option<&str>,
+    preferred_table: &str,
+) -> Result<String, MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+    create_sources_table(&conn).map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+
+    let registered_table = |condition: &str,
+                            param: &str|
+     -> Result<Option<String>, MetadataError> {
+        let query = format!(
+            "SELECT table_name FROM {SOURCES_TABLE} WHERE {condition} ORDER BY started_at DESC LIMIT 1"
+        );
+        match conn.query_row(&query, [param], |row| row.get(0)) {
+            Ok(table_name) => Ok(Some(table_name)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(MetadataError::SqlError(Box::new(e))),
+        }
+    };
+
+    let taken_tables =
+        load_taken_tables(&conn).map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+    let table_name = match alias {
+        Some(alias) => match registered_table("alias = ?1 COLLATE NOCASE", alias)? {
+            Some(table_name) => table_name,
+            None => {
+                if is_taken_table(alias, &taken_tables) {
+                    return Err(MetadataError::TableConflict(format!(
+                        "a table named {alias} already exists and does not belong to a source named {alias}"
+                    )));
+                }
+                alias.to_string()
+            }
+        },
+        None => match registered_table("command = ?1 AND alias IS NULL", command)? {
+            Some(table_name) => table_name,
+            None => {
+                let mut table_name = preferred_table.to_string();
+                let mut number = 1;
+                while is_taken_table(&table_name, &taken_tables) {
+                    number += 1;
+                    table_name = format!("{preferred_table}{TABLE_NUMBER_SEPARATOR}{number}");
+                }
+                table_name
+            }
+        },
+    };
+
+    let query = format!(
+        "INSERT INTO {SOURCES_TABLE} (source_id, table_name, command, alias, started_at, lines, unparsable_lines)
+            VALUES (?1, ?2, ?3, ?4, ?5, 0, 0)"
+    );
+    conn.execute(
+        &query,
+        rusqlite::params![
+            source_id,
+            table_name,
+            command,
+            alias,
+            format_timestamp(&Utc::now())
+        ],
+    )
+    .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+
+    Ok(table_name)
+}
+
+/// Loads the names of every registered source table and of every table, view and index in the DB, in ASCII lowercase.
+fn load_taken_tables(conn: &Connection) -> Result<HashSet<String>, rusqlite::Error> {
+    let query =
+        format!("SELECT table_name FROM {SOURCES_TABLE} UNION SELECT name FROM sqlite_master");
+    let mut stmt = conn.prepare(&query)?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .map(|name| name.map(|name| name.to_ascii_lowercase()))
+        .collect();
+    names
+}
+
+/// Whether a new source table named `name` would share a table with another source, given the `taken` names from `load_taken_tables`.
+///
+/// Besides the name itself, the tables a source derives from its table are checked both ways: `app` and `app_stderr` cannot be given
+/// to two sources, and `app` cannot be given while tables named like its child tables (`app__errors`) exist.
+fn is_taken_table(name: &str, taken: &HashSet<String>) -> bool {
+    let name = name.to_ascii_lowercase();
+    let stderr_table = format!("{name}{STDERR_TABLE_SUFFIX}");
+    let child_prefix = format!("{name}{CHILD_TABLE_SEPARATOR}");
+    is_reserved_table(&name)
+        || taken.contains(&name)
+        || taken.contains(&stderr_table)
+        || name
+            .strip_suffix(STDERR_TABLE_SUFFIX)
+            .is_some_and(|table| taken.contains(table))
+        || taken.iter().any(|other| other.starts_with(&child_prefix))
+}
+
+/// Whether `name` is one of logparsely's own tables, or one SQLite reserves.
+fn is_reserved_table(name: &str) -> bool {
+    name.to_ascii_lowercase().starts_with("sqlite_")
+        || [
+            FILE_OFFSETS_TABLE,
+            COLUMN_MAPPINGS_TABLE,
+            DEMOTED_KEYS_TABLE,
+            SOURCES_TABLE,
+            ALL_LOGS_VIEW,
+        ]
+        .iter()
+        .any(|own| own.eq_ignore_ascii_case(name))
+}
+
+/// Records that a table of the source stopped receiving lines, adding the lines it wrote to the source's counts.
+///
+/// A source writing into several tables (e.g. standard output and standard error) is ended by the last of them.
+pub fn record_source_end(
+    shared_connection: Arc<Mutex<Connection>>,
+    source_id: &str,
+    lines: u64,
+    unparsable_lines: u64,
+) -> Result<(), MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+
+    let query = format!(
+        "UPDATE {SOURCES_TABLE} SET ended_at = ?2, lines = lines + ?3, unparsable_lines = unparsable_lines + ?4 WHERE source_id = ?1"
+    );
+    conn.execute(
+        &query,
+        rusqlite::params![
+            source_id,
+            format_timestamp(&Utc::now()),
+            lines as i64,
+            unparsable_lines as i64
+        ],
+    )
+    .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+    Ok(())
+}
+
+/// Records the exit status of a command source, `None` if it was killed by a signal.
+pub fn record_source_exit(
+    shared_connection: Arc<Mutex<Connection>>,
+    source_id: &str,
+    exit_status: Option<i32>,
+) -> Result<(), MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+
+    let query = format!("UPDATE {SOURCES_TABLE} SET exit_status = ?2 WHERE source_id = ?1");
+    conn.execute(&query, rusqlite::params![source_id, exit_status])
+        .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+    Ok(())
+}
+
+fn create_sources_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = format!(
+        "CREATE TABLE IF NOT EXISTS {SOURCES_TABLE} (
+            source_id TEXT PRIMARY KEY,
+            table_name TEXT NOT NULL,
+            command TEXT NOT NULL,
+            alias TEXT,
+            started_at TEXT NOT NULL,
+            ended_at TEXT,
+            exit_status INTEGER,
+            lines INTEGER NOT NULL,
+            unparsable_lines INTEGER NOT NULL
+        )"
+    );
+    conn.execute(&query, ())?;
+    Ok(())
+}
+
+/// Converts a value demoted to the `_overflow` column into its JSON form.
+fn to_json_value(value: SqlValue) -> serde_json::Value {
+    match value {
+        SqlValue::Null => serde_json::Value::Null,
+        SqlValue::Integer(i) => serde_json::Value::from(i),
+        SqlValue::Real(f) => serde_json::Value::from(f),
+        SqlValue::Text(text) => serde_json::Value::String(text),
+        SqlValue::Blob(bytes) => {
+            serde_json::Value::String(String::from_utf8_lossy(&bytes).to_string())
+        }
+    }
+}
+
+/// Loads the keys demoted to overflow storage by every table, by table name, for the end of session report.
+pub fn load_all_demoted_keys(
+    shared_connection: Arc<Mutex<Connection>>,
+) -> Result<Vec<(String, Vec<String>)>, MetadataError> {
+    let conn = shared_connection
+        .lock()
+        .map_err(|e| MetadataError::LockError(e.to_string()))?;
+
+    create_demoted_keys_table(&conn).map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+
+    let query =
+        format!("SELECT table_name, key FROM {DEMOTED_KEYS_TABLE} ORDER BY table_name, key");
+    let mut stmt = conn
+        .prepare(&query)
+        .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+
+    let mut demoted: Vec<(String, Vec<String>)> = Vec::new();
+    for row in rows {
+        let (table_name, key) = row.map_err(|e| MetadataError::SqlError(Box::new(e)))?;
+        match demoted.last_mut() {
+            Some((last_table, keys)) if *last_table == table_name => keys.push(key),
+            _ => demoted.push((table_name, vec![key])),
+        }
+    }
+    Ok(demoted)
+}
+
+fn load_demoted_keys(
+    conn: &Connection,
+    table_name: &str,
+) -> Result<HashSet<String>, rusqlite::Error> {
+    create_demoted_keys_table(conn)?;
+
+    let query = format!("SELECT key FROM {DEMOTED_KEYS_TABLE} WHERE table_name = ?1");
+    let mut stmt = conn.prepare(&query)?;
+    let keys = stmt
+        .query_map([table_name], |row| row.get(0))?
+        .collect::<Result<HashSet<String>, _>>()?;
+    Ok(keys)
+}
+
+fn save_demoted_key(conn: &Connection, table_name: &str, key: &str) -> Result<(), rusqlite::Error> {
+    let query = format!(
+        "INSERT OR IGNORE INTO {DEMOTED_KEYS_TABLE} (table_name, key, demoted_at) VALUES (?1, ?2, datetime('now'))"
+    );
+    conn.execute(&query, [table_name, key])?;
+    Ok(())
+}
+
+fn create_demoted_keys_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let create_query = format!(
+        "CREATE TABLE IF NOT EXISTS {DEMOTED_KEYS_TABLE} (
+            table_name TEXT NOT NULL,
+            key TEXT NOT NULL,
+            demoted_at TEXT NOT NULL,
+            PRIMARY KEY (table_name, key)
+        )"
+    );
+    conn.execute(&create_query, ())?;
+    Ok(())
+}
+
+fn load_column_mappings(
+    conn: &Connection,
+    table_name: &str,
+) -> Result<HashMap<String, String>, rusqlite::Error> {
+    create_column_mappings_table(conn)?;
+
+    let query =
+        format!("SELECT key, column_name FROM {COLUMN_MAPPINGS_TABLE} WHERE table_name = ?1");
+    let mut stmt = conn.prepare(&query)?;
+    let mappings = stmt
+        .query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<String, String>, _>>()?;
+    Ok(mappings)
+}
+
+fn save_column_mapping(
+    conn: &Connection,
+    table_name: &str,
+    key: &str,
+    col: &str,
+) -> Result<(), rusqlite::Error> {
+    let query = format!(
+        "INSERT OR REPLACE INTO {COLUMN_MAPPINGS_TABLE} (table_name, key, column_name) VALUES (?1, ?2, ?3)"
+    );
+    conn.execute(&query, [table_name, key, col])?;
+    Ok(())
+}
+
+fn create_column_mappings_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let create_query = format!(
+        "CREATE TABLE IF NOT EXISTS {COLUMN_MAPPINGS_TABLE} (
+            table_name TEXT NOT NULL,
+            key TEXT NOT NULL,
+            column_name TEXT NOT NULL,
+            PRIMARY KEY (table_name, key)
+        )"
+    );
+    conn.execute(&create_query, ())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(options: TableOptions) -> (EvolvingWideTable, Arc<Mutex<Connection>>) {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let table = EvolvingWideTable::new("logs".to_string(), options, Arc::clone(&conn)).unwrap();
+        (table, conn)
+    }
+
+    fn row(fields: &[(&str, SqlValue)]) -> HashMap<String, SqlValue> {
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_case_collisions_are_merged_or_suffixed() {
+        let (mut merged, conn) = table(TableOptions::default());
+        let text = |s: &str| SqlValue::Text(s.to_string());
+        merged
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("Level", text("warn"))]),
+                Vec::new(),
+            )
+            .unwrap();
+        merged
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("level", text("info")), ("Level", text("error"))]),
+                Vec::new(),
+            )
+            .unwrap();
+        let levels: Vec<String> = conn
+            .lock()
+            .unwrap()
+            .prepare("SELECT Level FROM logs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(levels, vec!["warn", "error"]);
+
+        let (mut suffixed, conn) = table(TableOptions {
+            case_collision: CaseCollision::Suffix,
+            ..TableOptions::default()
+        });
+        suffixed
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("level", text("info"))]),
+                Vec::new(),
+            )
+            .unwrap();
+        suffixed
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("LEVEL", text("error"))]),
+                Vec::new(),
+            )
+            .unwrap();
+        let conn = conn.lock().unwrap();
+        let mapped: String = conn
+            .query_row(
+                "SELECT column_name FROM _column_mappings WHERE table_name = 'logs' AND key = 'LEVEL'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(mapped, "LEVEL__2");
+        let stored: String = conn
+            .query_row("SELECT LEVEL__2 FROM logs WHERE rowid = 2", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(stored, "error");
+    }
+
+    /// Returns the value and SQLite type of `col` in every row of `logs`, in insertion order.
+    fn typed_values(conn: &Connection, col: &str) -> Vec<(String, String)> {
+        conn.prepare(&format!(
+            "SELECT CAST({col} AS TEXT), typeof({col}) FROM logs ORDER BY _row_id"
+        ))
+        .unwrap()
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                row.get(1)?,
+            ))
+        })
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_keep_lets_the_column_affinity_convert_conflicting_values() {
+        let (mut wide_table, conn) = table(TableOptions::default());
+        for value in [
+            SqlValue::Integer(12),
+            SqlValue::Text("34".to_string()),
+            SqlValue::Text("n/a".to_string()),
+        ] {
+            wide_table
+                .insert_data(Arc::clone(&conn), row(&[("latency", value)]), Vec::new())
+                .unwrap();
+        }
+
+        let conn = conn.lock().unwrap();
+        assert_eq!(
+            typed_values(&conn, "latency"),
+            vec![
+                ("12".to_string(), "integer".to_string()),
+                ("34".to_string(), "integer".to_string()),
+                ("n/a".to_string(), "text".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_moves_conflicting_values_to_typed_siblings() {
+        let (mut wide_table, conn) = table(TableOptions {
+            type_conflict: TypeConflict::Split,
+            ..TableOptions::default()
+        });
+        for value in [
+            SqlValue::Integer(12),
+            SqlValue::Real(1.5),
+            SqlValue::Text("n/a".to_string()),
+        ] {
+            wide_table
+                .insert_data(Arc::clone(&conn), row(&[("latency", value)]), Vec::new())
+                .unwrap();
+        }
+
+        let conn = conn.lock().unwrap();
+        assert_eq!(
+            typed_values(&conn, "latency"),
+            vec![
+                ("12".to_string(), "integer".to_string()),
+                ("1.5".to_string(), "real".to_string()),
+                (String::new(), "null".to_string()),
+            ]
+        );
+        assert_eq!(
+            typed_values(&conn, "latency__text")[2],
+            ("n/a".to_string(), "text".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reopened_tables_recover_column_affinities() {
+        assert_eq!(
+            ColumnAffinity::from_declared_type("BIGINT"),
+            ColumnAffinity::Integer
+        );
+        assert_eq!(
+            ColumnAffinity::from_declared_type("varchar(10)"),
+            ColumnAffinity::Text
+        );
+        assert_eq!(
+            ColumnAffinity::from_declared_type("DOUBLE"),
+            ColumnAffinity::Real
+        );
+        assert_eq!(ColumnAffinity::from_declared_type(""), ColumnAffinity::Blob);
+
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("logs.db");
+        let options = TableOptions {
+            type_conflict: TypeConflict::Split,
+            ..TableOptions::default()
+        };
+        let open = || {
+            let conn = Arc::new(Mutex::new(Connection::open(&path).unwrap()));
+            let table =
+                EvolvingWideTable::new("logs".to_string(), options.clone(), Arc::clone(&conn))
+                    .unwrap();
+            (table, conn)
+        };
+
+        let (mut first_run, conn) = open();
+        first_run
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("latency", SqlValue::Integer(12)), ("msg", SqlValue::Null)]),
+                Vec::new(),
+            )
+            .unwrap();
+        drop((first_run, conn));
+
+        let (mut second_run, conn) = open();
+        assert_eq!(second_run.col_lookup["latency"], ColumnAffinity::Integer);
+        assert_eq!(second_run.col_lookup["msg"], ColumnAffinity::Blob);
+        second_run
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[
+                    ("latency", SqlValue::Text("n/a".to_string())),
+                    ("msg", SqlValue::Text("any type".to_string())),
+                ]),
+                Vec::new(),
+            )
+            .unwrap();
+        assert!(second_run.col_lookup.contains_key("latency__text"));
+        assert!(!second_run.col_lookup.contains_key("msg__text"));
+        drop((second_run, conn));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keys_past_the_column_budget_overflow() {
+        // _row_id, raw_unparsable_line, a and the overflow column
+        let (mut wide_table, conn) = table(TableOptions {
+            max_columns: 4,
+            ..TableOptions::default()
+        });
+        wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("a", SqlValue::Integer(1)), ("b", SqlValue::Integer(2))]),
+                Vec::new(),
+            )
+            .unwrap();
+        wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("a", SqlValue::Integer(3)), ("b", SqlValue::Integer(4))]),
+                Vec::new(),
+            )
+            .unwrap();
+
+        let conn = conn.lock().unwrap();
+        let rows: Vec<(i64, String)> = conn
+            .prepare("SELECT a, _overflow FROM logs")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![(1, "{\"b\":2}".to_string()), (3, "{\"b\":4}".to_string())]
+        );
+        assert_eq!(
+            load_demoted_keys(&conn, "logs").unwrap(),
+            HashSet::from(["b".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_overflow_table_is_apart_from_child_tables() {
+        let (mut wide_table, conn) = table(TableOptions {
+            max_columns: 4,
+            overflow: Overflow::Table,
+            ..TableOptions::default()
+        });
+        wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("a", SqlValue::Integer(1)), ("b", SqlValue::Integer(2))]),
+                vec![(
+                    "overflow".to_string(),
+                    row(&[("value", SqlValue::Integer(3))]),
+                )],
+            )
+            .unwrap();
+        assert!(wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("a", SqlValue::Integer(4))]),
+                vec![(
+                    "_Overflow".to_string(),
+                    row(&[("value", SqlValue::Integer(5))])
+                )],
+            )
+            .is_err());
+
+        let conn = conn.lock().unwrap();
+        let demoted: (String, i64) = conn
+            .query_row("SELECT key, value FROM logs___overflow", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(demoted, ("b".to_string(), 2));
+        let elements: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs__overflow", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(elements, 1);
+    }
+
+    #[test]
+    fn test_children_link_to_row_ids_and_roll_back_with_their_row() {
+        let (mut wide_table, conn) = table(TableOptions::default());
+        let parent_id = wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("msg", SqlValue::Text("first".to_string()))]),
+                vec![("errors".to_string(), row(&[("code", SqlValue::Integer(1))]))],
+            )
+            .unwrap();
+        // the second child row is refused, its table name is reserved for overflow storage
+        assert!(wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[
+                    ("msg", SqlValue::Text("second".to_string())),
+                    ("new_field", SqlValue::Integer(2))
+                ]),
+                vec![
+                    ("errors".to_string(), row(&[("code", SqlValue::Integer(2))])),
+                    (
+                        "_overflow".to_string(),
+                        row(&[("code", SqlValue::Integer(3))])
+                    ),
+                ],
+            )
+            .is_err());
+        // a record with a field named like the row id column keeps both
+        let row_id = wide_table
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("_row_id", SqlValue::Text("mine".to_string()))]),
+                Vec::new(),
+            )
+            .unwrap();
+
+        let conn = conn.lock().unwrap();
+        let rows: Vec<(i64, Option<String>)> = conn
+            .prepare("SELECT _row_id, msg FROM logs ORDER BY _row_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![(parent_id, Some("first".to_string())), (row_id, None)]
+        );
+        let links: Vec<i64> = conn
+            .prepare("SELECT _parent_id FROM logs__errors")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(links, vec![parent_id]);
+        assert!(!wide_table.col_lookup.contains_key("new_field"));
+        let own: String = conn
+            .query_row(
+                "SELECT _row_id__2 FROM logs WHERE _row_id = ?",
+                [row_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(own, "mine");
+    }
+
+    #[test]
+    fn test_new_sources_get_numbered_tables_unless_their_command_ran_before() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let register = |id: &str, command: &str, preferred: &str| {
+            register_source(Arc::clone(&conn), id, command, None, preferred).unwrap()
+        };
+        assert_eq!(register("1", "cat a.log", "cat_a_log"), "cat_a_log");
+        assert_eq!(register("2", "cat a-log", "cat_a_log"), "cat_a_log_2");
+        assert_eq!(register("3", "CAT a.log", "CAT_a_log"), "CAT_a_log_3");
+        assert_eq!(register("4", "cat a-log", "cat_a_log"), "cat_a_log_2");
+        assert_eq!(register("5", "sources", "_Sources"), "_Sources_2");
+    }
+
+    #[test]
+    fn test_new_sources_keep_clear_of_derived_and_unregistered_tables() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let register = |id: &str, command: &str, preferred: &str| {
+            register_source(Arc::clone(&conn), id, command, None, preferred).unwrap()
+        };
+        // the standard error table of one source is not given to another, whichever registers first
+        assert_eq!(register("1", "app", "app"), "app");
+        assert_eq!(register("2", "app stderr", "app_stderr"), "app_stderr_2");
+        assert_eq!(register("3", "web stderr", "web_stderr"), "web_stderr");
+        assert_eq!(register("4", "web", "web"), "web_2");
+
+        conn.lock()
+            .unwrap()
+            .execute_batch("CREATE TABLE db (a); CREATE TABLE api__errors (a);")
+            .unwrap();
+        assert_eq!(register("5", "db", "db"), "db_2");
+        assert_eq!(register("6", "api", "api"), "api_2");
+        assert!(matches!(
+            register_source(Arc::clone(&conn), "7", "true", Some("App_Stderr"), "unused"),
+            Err(MetadataError::TableConflict(_))
+        ));
+    }
+
+    #[test]
+    fn test_quoted_identifiers_hold_any_name() {
+        let (_, conn) = table(TableOptions::default());
+        let mut quoted = EvolvingWideTable::new(
+            "echo '\"a\"; DROP TABLE x'".to_string(),
+            TableOptions::default(),
+            Arc::clone(&conn),
+        )
+        .unwrap();
+        quoted
+            .insert_data(
+                Arc::clone(&conn),
+                row(&[("we\"ird`key", SqlValue::Integer(1))]),
+                Vec::new(),
+            )
+            .unwrap();
+        let stored: i64 = conn
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM {}",
+                    quote_identifier("we\"ird`key"),
+                    quote_identifier("echo '\"a\"; DROP TABLE x'")
+                ),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[test]
+    fn test_source_end_adds_up_the_lines_of_every_table() {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        register_source(Arc::clone(&conn), "1", "app", None, "app").unwrap();
+        record_source_end(Arc::clone(&conn), "1", 3, 1).unwrap();
+        record_source_end(Arc::clone(&conn), "1", 2, 0).unwrap();
+        let lines: (i64, i64) = conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT lines, unparsable_lines FROM _sources WHERE source_id = '1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(lines, (5, 1));
+    }
+
+    #[test]
+    fn test_aliases_name_tables_unless_taken() {
+        let (_, conn) = table(TableOptions::default());
+        let register = |id: &str, command: &str, alias: &str| {
+            register_source(Arc::clone(&conn), id, command, Some(alias), "unused")
+        };
+        assert_eq!(register("1", "docker logs web -f", "web").unwrap(), "web");
+        // the table follows the alias when it is given another command
+        assert_eq!(register("2", "kubectl logs web -f", "Web").unwrap(), "web");
+
+        let unaliased = register_source(Arc::clone(&conn), "3", "cat web", None, "api").unwrap();
+        assert_eq!(unaliased, "api");
+        for (id, taken) in [("4", "api"), ("5", "logs"), ("6", "all_logs")] {
+            assert!(matches!(
+                register(id, "true", taken),
+                Err(MetadataError::TableConflict(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides syslog ingestion functionality.
+///
+/// This module defines a `parse_syslog` function that turns RFC 3164 and RFC 5424 frames into JSON objects,
+/// and UDP/TCP listeners that feed received frames into the ingestion pipeline.
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::concurrency_helper::SharedState;
+use crate::ingestion::SourceLine;
+
+/// Column recording the address a syslog frame was received from.
+pub const PEER_COL: &str = "_peer";
+/// Column recording whether a syslog frame was received over UDP or TCP.
+pub const TRANSPORT_COL: &str = "_transport";
+
+const MAX_UDP_DATAGRAM: usize = 65535;
+/// The largest TCP frame accepted, the same as the largest datagram. A connection sending a larger one is dropped.
+const MAX_TCP_FRAME: usize = MAX_UDP_DATAGRAM;
+/// Digits of an octet count plus its trailing space, more than any count up to `MAX_TCP_FRAME` needs.
+const MAX_OCTET_COUNT_LEN: u64 = 8;
+/// How often a UDP listener waiting for datagrams checks the stop signal.
+const UDP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a UDP listener waits after a receive error, doubled on every consecutive error up to `MAX_UDP_ERROR_BACKOFF`.
+const UDP_ERROR_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_UDP_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+const NIL_VALUE: &str = "-";
+const BOM: &str = "\u{feff}";
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses a single syslog frame into a JSON object.
+///
+/// Frames with a version after the priority are parsed as RFC 5424, anything else as RFC 3164 (BSD syslog).
+/// Structured data elements end up nested under `sd`, so flattening turns them into `sd.<id>.<param>` columns.
+///
+/// Returns `None` if the frame does not start with a `<PRI>` header.
+pub fn parse_syslog(frame: &str) -> Option<Value> {
+    let frame = frame.trim_end_matches(['\n', '\r', '\0']);
+    let (pri, rest) = parse_pri(frame)?;
+
+    let mut record = Map::new();
+    record.insert("pri".to_string(), Value::from(pri));
+    record.insert(
+        "facility".to_string(),
+        Value::from(FACILITIES[(pri / 8) as usize]),
+    );
+    record.insert(
+        "severity".to_string(),
+        Value::from(SEVERITIES[(pri % 8) as usize]),
+    );
+
+    match rest.split_once(' ') {
+        Some((version, body))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            record.insert(
+                "version".to_string(),
+                Value::from(version.parse::<u64>().ok()?),
+            );
+            parse_rfc5424(body, &mut record);
+        }
+        _ => parse_rfc3164(rest, &mut record),
+    }
+
+    Some(Value::Object(record))
+}
+
+fn parse_pri(frame: &str) -> Option<(u8, &str)> {
+    let rest = frame.strip_prefix('<')?;
+    let (digits, rest) = rest.split_once('>')?;
+    if digits.is_empty() || digits.len() > 3 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let pri = digits.parse::<u8>().ok().filter(|pri| *pri <= 191)?;
+    Some((pri, rest))
+}
+
+// TIMESTAMP SP HOSTNAME SP APP-NAME SP PROCID SP MSGID SP STRUCTURED-DATA [SP MSG]
+fn parse_rfc5424(body: &str, record: &mut Map<String, Value>) {
+    let mut rest = body;
+    for field in ["timestamp", "hostname", "app_name", "procid", "msgid"] {
+        let (value, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+        insert_unless_nil(record, field, value);
+        rest = remainder;
+    }
+
+    let message = if let Some(remainder) = rest.strip_prefix(NIL_VALUE) {
+        remainder
+    } else {
+        let (sd, remainder) = parse_structured_data(rest);
+        if !sd.is_empty() {
+            record.insert("sd".to_string(), Value::Object(sd));
+        }
+        remainder
+    };
+
+    let message = message.strip_prefix(' ').unwrap_or(message);
+    let message = message.strip_prefix(BOM).unwrap_or(message);
+    if !message.is_empty() {
+        record.insert("message".to_string(), Value::from(message));
+    }
+}
+
+/// Parses `[id param="value" ...]...` elements, returns them keyed by SD-ID along with the unparsed remainder.
+fn parse_structured_data(input: &str) -> (Map<String, Value>, &str) {
+    let mut elements = Map::new();
+    let mut rest = input;
+
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = match element.find([' ', ']']) {
+            Some(end) => end,
+            None => break,
+        };
+        let id = &element[..id_end];
+        let mut params = Map::new();
+        let mut cursor = &element[id_end..];
+
+        loop {
+            cursor = cursor.trim_start_matches(' ');
+            if let Some(after) = cursor.strip_prefix(']') {
+                cursor = after;
+                break;
+            }
+            let (name, after_name) = match cursor.split_once("=\"") {
+                Some(split) => split,
+                None => return (elements, rest),
+            };
+            let (value, after_value) = match parse_param_value(after_name) {
+                Some(parsed) => parsed,
+                None => return (elements, rest),
+            };
+            params.insert(name.to_string(), Value::from(value));
+            cursor = after_value;
+        }
+
+        elements.insert(id.to_string(), Value::Object(params));
+        rest = cursor;
+    }
+
+    (elements, rest)
+}
+
+/// Reads a quoted PARAM-VALUE up to its closing quote, unescaping `\"`, `\\` and `\]`.
+fn parse_param_value(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | ']'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => return None,
+            },
+            '"' => return Some((value, &input[i + 1..])),
+            other => value.push(other),
+        }
+    }
+    None
+}
+
+// [TIMESTAMP SP HOSTNAME SP] TAG[PID]: MSG, with relays often dropping the hostname or the whole header
+fn parse_rfc3164(body: &str, record: &mut Map<String, Value>) {
+    let mut rest = body;
+
+    if let Some((timestamp, remainder)) = split_bsd_timestamp(rest) {
+        record.insert("timestamp".to_string(), Value::from(timestamp));
+        rest = remainder;
+
+        if let Some((host, remainder)) = rest.split_once(' ') {
+            if !looks_like_tag(host) {
+                insert_unless_nil(record, "hostname", host);
+                rest = remainder;
+            }
+        }
+    }
+
+    let tag_end = rest.find([':', '[', ' ']);
+    match tag_end {
+        Some(end) if end > 0 && rest[end..].starts_with(['[', ':']) => {
+            record.insert("app_name".to_string(), Value::from(&rest[..end]));
+            rest = &rest[end..];
+            if let Some(after) = rest.strip_prefix('[') {
+                if let Some((pid, remainder)) = after.split_once(']') {
+                    record.insert("procid".to_string(), Value::from(pid));
+                    rest = remainder;
+                }
+            }
+            rest = rest.strip_prefix(':').unwrap_or(rest);
+            rest = rest.strip_prefix(' ').unwrap_or(rest);
+        }
+        _ => {}
+    }
+
+    if !rest.is_empty() {
+        record.insert("message".to_string(), Value::from(rest));
+    }
+}
+
+/// Splits off a `Mmm dd hh:mm:ss` timestamp, or an RFC 3339 one as sent by some relays.
+fn split_bsd_timestamp(input: &str) -> Option<(&str, &str)> {
+    if input.len() >= 16 && input.is_char_boundary(15) && MONTHS.contains(&input.get(..3)?) {
+        let (timestamp, rest) = input.split_at(15);
+        let bytes = timestamp.as_bytes();
+        if bytes[3] == b' ' && bytes[9] == b':' && bytes[12] == b':' {
+            return Some((timestamp, rest.strip_prefix(' ')?));
+        }
+    }
+
+    let (token, rest) = input.split_once(' ')?;
+    let bytes = token.as_bytes();
+    if bytes.len() >= 19
+        && bytes[..4].iter().all(u8::is_ascii_digit)
+        && bytes[4] == b'-'
+        && bytes[10] == b'T'
+    {
+        return Some((token, rest));
+    }
+    None
+}
+
+fn looks_like_tag(token: &str) -> bool {
+    token.ends_with(':') || token.contains('[')
+}
+
+fn insert_unless_nil(record: &mut Map<String, Value>, field: &str, value: &str) {
+    if !value.is_empty() && value != NIL_VALUE {
+        record.insert(field.to_string(), Value::from(value));
+    }
+}
+
+fn frame_line(frame: String, peer: String, transport: &str) -> SourceLine {
+    SourceLine {
+        text: frame,
+        tags: HashMap::from([
+            (PEER_COL.to_string(), peer),
+            (TRANSPORT_COL.to_string(), transport.to_string()),
+        ]),
+    }
+}
+
+/// Binds a UDP socket on `addr` and sends every received datagram into `sender` as one frame.
+///
+/// The listener runs on a detached thread until the stop signal is set, or `sender`'s receiver is dropped.
+/// Receive errors are reported and retried after a growing backoff.
+pub fn spawn_udp_listener(
+    addr: &str,
+    sender: Sender<SourceLine>,
+    signal: Arc<SharedState>,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_read_timeout(Some(UDP_POLL_INTERVAL))?;
+    println!("Listening for syslog over UDP on {}", socket.local_addr()?);
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+        let mut backoff = UDP_ERROR_BACKOFF;
+        while !signal.is_stopped() {
+            let (len, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    eprintln!("Error receiving syslog datagram: {}", e);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_UDP_ERROR_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = UDP_ERROR_BACKOFF;
+            let frame = String::from_utf8_lossy(&buf[..len]).to_string();
+            if sender
+                .send(frame_line(frame, peer.to_string(), "udp"))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Binds a TCP listener on `addr`, each connection gets a thread that splits its stream into frames and sends them into `sender`.
+///
+/// Both octet-counted (`LEN SP MSG`, RFC 6587) and newline delimited framing are accepted, detected per frame.
+pub fn spawn_tcp_listener(addr: &str, sender: Sender<SourceLine>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!(
+        "Listening for syslog over TCP on {}",
+        listener.local_addr()?
+    );
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Error accepting syslog connection: {}", e);
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            thread::spawn(move || handle_tcp_connection(stream, sender));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_tcp_connection(stream: TcpStream, sender: Sender<SourceLine>) {
+    let peer = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        match read_tcp_frame(&mut reader) {
+            Ok(Some(frame)) => {
+                if sender.send(frame_line(frame, peer.clone(), "tcp")).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Error reading syslog frame from {}: {}", peer, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Reads the next frame from a TCP stream, `None` once the stream is closed.
+///
+/// Frames larger than `MAX_TCP_FRAME` are an error, the peer is not trusted with how much memory a frame takes.
+fn read_tcp_frame(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    // skip trailers left over from the previous frame
+    loop {
+        let buf = reader.fill_buf()?;
+        match buf.first() {
+            None => return Ok(None),
+            Some(b'\n' | b'\r' | b'\0') => reader.consume(1),
+            Some(_) => break,
+        }
+    }
+
+    let starts_with_digit = reader.fill_buf()?[0].is_ascii_digit();
+    let mut frame = Vec::new();
+    if starts_with_digit {
+        let mut len = Vec::new();
+        reader
+            .by_ref()
+            .take(MAX_OCTET_COUNT_LEN)
+            .read_until(b' ', &mut len)?;
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .filter(|len| len.ends_with(' '))
+            .and_then(|len| len.trim_end().parse::<usize>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid octet count"))?;
+        if len > MAX_TCP_FRAME {
+            return Err(frame_too_large());
+        }
+        frame.resize(len, 0);
+        reader.read_exact(&mut frame)?;
+    } else {
+        reader
+            .by_ref()
+            .take(MAX_TCP_FRAME as u64 + 1)
+            .read_until(b'\n', &mut frame)?;
+        if frame.len() > MAX_TCP_FRAME {
+            return Err(frame_too_large());
+        }
+    }
+
+    Ok(Some(String::from_utf8_lossy(&frame).to_string()))
+}
+
+fn frame_too_large() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("frame larger than {MAX_TCP_FRAME} bytes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rfc5424_with_structured_data() {
+        let frame = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="Appl\"ication"][meta sequenceId="1"] An application event"#;
+        let parsed = parse_syslog(frame).unwrap();
+        assert_eq!(
+            parsed,
+            json!({
+                "pri": 165,
+                "facility": "local4",
+                "severity": "notice",
+                "version": 1,
+                "timestamp": "2003-10-11T22:14:15.003Z",
+                "hostname": "mymachine.example.com",
+                "app_name": "evntslog",
+                "msgid": "ID47",
+                "sd": {
+                    "exampleSDID@32473": {"iut": "3", "eventSource": "Appl\"ication"},
+                    "meta": {"sequenceId": "1"}
+                },
+                "message": "An application event"
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3164() {
+        let parsed =
+            parse_syslog("<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed\n").unwrap();
+        assert_eq!(parsed["facility"], "auth");
+        assert_eq!(parsed["severity"], "crit");
+        assert_eq!(parsed["timestamp"], "Oct 11 22:14:15");
+        assert_eq!(parsed["hostname"], "mymachine");
+        assert_eq!(parsed["app_name"], "su");
+        assert_eq!(parsed["procid"], "123");
+        assert_eq!(parsed["message"], "'su root' failed");
+
+        // relays often drop the hostname
+        let parsed = parse_syslog("<13>Feb  5 17:32:18 cron: job done").unwrap();
+        assert_eq!(parsed.get("hostname"), None);
+        assert_eq!(parsed["app_name"], "cron");
+        assert_eq!(parsed["message"], "job done");
+
+        assert_eq!(parse_syslog("no priority here"), None);
+    }
+
+    #[test]
+    fn test_read_tcp_frames_octet_counted_and_newline_delimited() {
+        let stream = b"11 <13>1 - - -12 <13>hi there\n<14>plain frame\n";
+        let mut reader = BufReader::new(&stream[..]);
+        assert_eq!(read_tcp_frame(&mut reader).unwrap().unwrap(), "<13>1 - - -");
+        assert_eq!(
+            read_tcp_frame(&mut reader).unwrap().unwrap(),
+            "<13>hi there"
+        );
+        assert_eq!(
+            read_tcp_frame(&mut reader).unwrap().unwrap(),
+            "<14>plain frame\n"
+        );
+        assert_eq!(read_tcp_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_tcp_frame_rejects_oversized_frames() {
+        for stream in [
+            b"9999999999999 <13>hi".to_vec(),
+            format!("{} <13>hi", MAX_TCP_FRAME + 1).into_bytes(),
+            vec![b'x'; MAX_TCP_FRAME + 10],
+        ] {
+            let mut reader = BufReader::new(&stream[..]);
+            let err = read_tcp_frame(&mut reader).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
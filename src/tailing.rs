@@ -0,0 +1,475 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides native file tailing functionality.
+///
+/// This module defines a `FileTailer` that follows a file like `tail -F` does, without shelling out,
+/// and a `watch_glob` function that starts following files as they appear.
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata};
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::archive::{is_archive_of, is_compressed, ArchiveReader};
+use crate::concurrency_helper::SharedState;
+use crate::storage::{load_file_offset, save_file_offset, FileOffset, MetadataError};
+
+const TAIL_POLL_INTERVAL_MS: u64 = 250;
+const GLOB_POLL_INTERVAL_MS: u64 = 1000;
+
+/// Follows a file line by line, surviving log rotation.
+///
+/// The tailer is an iterator over complete lines. When it reaches the end of the file it polls for new data
+/// until the shared stop signal is set, at which point the iterator ends.
+///
+/// Two rotation schemes are handled:
+/// * rename + recreate: the file at `path` gets a new identity (inode), the old handle is drained and the new file is read from the start.
+/// * copytruncate: the file at `path` shrinks below the current offset, reading restarts from the start.
+///
+/// The offset just past the last complete line is persisted in the DB, so a later run against the same DB resumes where this one stopped.
+pub struct FileTailer {
+    /// The path being followed, also used as the key for the persisted offset.
+    path: PathBuf,
+    /// The currently open file, if it exists yet.
+    reader: Option<BufReader<File>>,
+    /// Identity of the currently open file.
+    file_id: Option<u64>,
+    /// Byte offset of everything read so far, including `pending`.
+    offset: u64,
+    /// Bytes of a line that has not been terminated by a newline yet.
+    pending: Vec<u8>,
+    /// The last offset written to the DB, to avoid rewriting it on every poll.
+    persisted: Option<FileOffset>,
+    shared_connection: Arc<Mutex<Connection>>,
+    signal: Arc<SharedState>,
+}
+
+impl FileTailer {
+    /// Creates a tailer for `path`, resuming from the persisted offset if it still refers to the same file.
+    ///
+    /// The file does not need to exist yet, the tailer waits for it to appear.
+    pub fn new(
+        path: PathBuf,
+        shared_connection: Arc<Mutex<Connection>>,
+        signal: Arc<SharedState>,
+    ) -> Result<FileTailer, MetadataError> {
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        let persisted = load_file_offset(Arc::clone(&shared_connection), &path.to_string_lossy())?;
+
+        let mut tailer = FileTailer {
+            path,
+            reader: None,
+            file_id: None,
+            offset: 0,
+            pending: Vec::new(),
+            persisted,
+            shared_connection,
+            signal,
+        };
+
+        if tailer.open().is_none() {
+            println!(
+                "File {} does not exist yet, waiting for it to appear",
+                tailer.path.display()
+            );
+        }
+
+        Ok(tailer)
+    }
+
+    /// Opens the file at `path`, seeking to the persisted offset if it belongs to this file.
+    fn open(&mut self) -> Option<()> {
+        let file = File::open(&self.path).ok()?;
+        let meta = file.metadata().ok()?;
+        let file_id = file_id(&meta);
+
+        let resume_at = match self.persisted {
+            Some(saved) if saved.file_id == file_id && saved.offset <= meta.len() => saved.offset,
+            _ => 0,
+        };
+
+        let mut reader = BufReader::new(file);
+        if reader.seek(SeekFrom::Start(resume_at)).is_err() {
+            return None;
+        }
+
+        self.reader = Some(reader);
+        self.file_id = file_id;
+        self.offset = resume_at;
+        self.pending.clear();
+        Some(())
+    }
+
+    /// Offset just past the last complete line handed out.
+    fn committed_offset(&self) -> FileOffset {
+        FileOffset {
+            file_id: self.file_id,
+            offset: self.offset - self.pending.len() as u64,
+        }
+    }
+
+    fn persist(&mut self) {
+        let current = self.committed_offset();
+        if self.persisted == Some(current) {
+            return;
+        }
+
+        let path = self.path.to_string_lossy().to_string();
+        match save_file_offset(Arc::clone(&self.shared_connection), &path, current) {
+            Ok(()) => self.persisted = Some(current),
+            Err(e) => eprintln!("Failed to persist offset for {}: {}", path, e),
+        }
+    }
+
+    /// Called on EOF, checks whether the file was rotated or truncated and reopens it if needed.
+    ///
+    /// Returns the unterminated remainder of the old file if it was rotated away, since no more data will be appended to it.
+    fn handle_rotation(&mut self) -> Option<String> {
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            // renamed away and not recreated yet, keep the old handle until a new file appears
+            Err(_) => return None,
+        };
+
+        if self.reader.is_some() && file_id(&meta) != self.file_id {
+            println!("File {} was rotated, reopening", self.path.display());
+            let remainder = self.take_pending();
+            self.persisted = None;
+            self.reader = None;
+            self.open();
+            return remainder;
+        }
+
+        if meta.len() < self.offset {
+            println!(
+                "File {} was truncated, reading from the start",
+                self.path.display()
+            );
+            if let Some(reader) = self.reader.as_mut() {
+                if reader.seek(SeekFrom::Start(0)).is_ok() {
+                    self.offset = 0;
+                    self.pending.clear();
+                }
+            }
+        }
+
+        None
+    }
+
+    fn take_pending(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = to_line(&self.pending);
+        self.pending.clear();
+        Some(line)
+    }
+}
+
+impl Iterator for FileTailer {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if self.signal.is_stopped() {
+                self.persist();
+                return None;
+            }
+
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => {
+                    if self.open().is_none() {
+                        self.signal
+                            .wait_for_stop_signal_timeout(Duration::from_millis(
+                                TAIL_POLL_INTERVAL_MS,
+                            ));
+                    }
+                    continue;
+                }
+            };
+
+            match reader.read_until(b'\n', &mut self.pending) {
+                Ok(0) => {
+                    self.persist();
+                    if let Some(remainder) = self.handle_rotation() {
+                        return Some(remainder);
+                    }
+                    self.signal
+                        .wait_for_stop_signal_timeout(Duration::from_millis(TAIL_POLL_INTERVAL_MS));
+                }
+                Ok(n) => {
+                    self.offset += n as u64;
+                    if self.pending.ends_with(b"\n") {
+                        return self.take_pending();
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", self.path.display(), e);
+                    self.signal
+                        .wait_for_stop_signal_timeout(Duration::from_millis(TAIL_POLL_INTERVAL_MS));
+                }
+            }
+        }
+    }
+}
+
+/// Follows every file matching `pattern`, including files created after the watch started, until the stop signal is set.
+///
+/// Each file gets its own `FileTailer` thread (or `ArchiveReader` for compressed files), all of them hand their lines to `sink`
+/// along with the path of their file, and stop once it returns `false`.
+/// Rotated archives of another matching file (`app.log.1`, `app.log.2.gz`) are skipped, the tailer of the live file reads
+/// what was rotated away, so `*.log*` or a directory does not ingest every rotation a second time.
+/// The function returns once the stop signal is set, the tailer threads hold their own clones of `sink` and stop on the same signal.
+pub fn watch_glob<F>(
+    pattern: &str,
+    sink: F,
+    shared_connection: Arc<Mutex<Connection>>,
+    signal: Arc<SharedState>,
+) where
+    F: Fn(&Path, String) -> bool + Clone + Send + 'static,
+{
+    let mut watched: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths,
+            Err(e) => {
+                eprintln!("Invalid glob pattern {}: {}", pattern, e);
+                return;
+            }
+        };
+
+        let paths: Vec<PathBuf> = paths.flatten().filter(|path| path.is_file()).collect();
+        for path in &paths {
+            if watched.contains(path) {
+                continue;
+            }
+            // files watched earlier count too, the live file may not have been recreated yet when its archive shows up
+            let rotated = watched
+                .iter()
+                .chain(&paths)
+                .any(|live| is_archive_of(path, live));
+            watched.insert(path.clone());
+            if rotated {
+                continue;
+            }
+
+            let lines = match open_file_lines(
+                path.clone(),
+                Arc::clone(&shared_connection),
+                Arc::clone(&signal),
+            ) {
+                Ok(lines) => lines,
+                Err(e) => {
+                    eprintln!("Failed to follow {}: {:?}", path.display(), e);
+                    continue;
+                }
+            };
+
+            println!("Following new file {}", path.display());
+
+            let path = path.clone();
+            let sink = sink.clone();
+            thread::spawn(move || {
+                for line in lines {
+                    if !sink(&path, line) {
+                        break;
+                    }
+                }
+            });
+        }
+
+        if signal.wait_for_stop_signal_timeout(Duration::from_millis(GLOB_POLL_INTERVAL_MS)) {
+            return;
+        }
+    }
+}
+
+/// Opens the lines of a file, reading it once if it is a compressed archive and following it otherwise.
+pub fn open_file_lines(
+    path: PathBuf,
+    shared_connection: Arc<Mutex<Connection>>,
+    signal: Arc<SharedState>,
+) -> Result<Box<dyn Iterator<Item = String> + Send>, MetadataError> {
+    if is_compressed(&path) {
+        Ok(Box::new(ArchiveReader::new(
+            path,
+            None,
+            shared_connection,
+            signal,
+        )))
+    } else {
+        Ok(Box::new(FileTailer::new(path, shared_connection, signal)?))
+    }
+}
+
+fn to_line(bytes: &[u8]) -> String {
+    let line = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).to_string()
+}
+
+/// Identity of a file that survives renames, used to tell a rotated file from the one that replaced it.
+#[cfg(unix)]
+pub fn file_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+// without inodes a recreated file is picked up through the truncation check instead
+#[cfg(not(unix))]
+pub fn file_id(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn tailer(path: &Path, conn: &Arc<Mutex<Connection>>, signal: &Arc<SharedState>) -> FileTailer {
+        FileTailer::new(path.to_path_buf(), Arc::clone(conn), Arc::clone(signal)).unwrap()
+    }
+
+    fn append(path: &Path, text: &str) {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_partial_lines_are_not_committed_and_a_later_run_resumes() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let path = dir.join("app.log");
+        fs::write(&path, "one\ntwo\npart").unwrap();
+
+        let signal = Arc::new(SharedState::new());
+        let mut first = tailer(&path, &conn, &signal);
+        assert_eq!(first.next().as_deref(), Some("one"));
+        assert_eq!(first.next().as_deref(), Some("two"));
+        // the unterminated line is read at EOF, then the tailer polls until it is stopped
+        let stop = thread::spawn({
+            let signal = Arc::clone(&signal);
+            move || {
+                thread::sleep(Duration::from_millis(2 * TAIL_POLL_INTERVAL_MS));
+                signal.stop();
+            }
+        });
+        assert_eq!(first.next(), None);
+        stop.join().unwrap();
+        let saved = load_file_offset(
+            Arc::clone(&conn),
+            &fs::canonicalize(&path).unwrap().to_string_lossy(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(saved.offset, "one\ntwo\n".len() as u64);
+
+        append(&path, "ial\nthree\n");
+        let mut second = tailer(&path, &conn, &Arc::new(SharedState::new()));
+        assert_eq!(second.next().as_deref(), Some("partial"));
+        assert_eq!(second.next().as_deref(), Some("three"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rotation_is_detected_through_a_new_file_id() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let path = dir.join("app.log");
+        fs::write(&path, "one\n").unwrap();
+
+        let mut tailer = tailer(&path, &conn, &Arc::new(SharedState::new()));
+        assert_eq!(tailer.next().as_deref(), Some("one"));
+        // the rotated file gets a last unterminated line, the new file is longer than what was read so far
+        append(&path, "two");
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "three\nfour\n").unwrap();
+        assert_eq!(tailer.next().as_deref(), Some("two"));
+        assert_eq!(tailer.next().as_deref(), Some("three"));
+        assert_eq!(tailer.next().as_deref(), Some("four"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_follows_new_files_but_not_rotated_ones() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let signal = Arc::new(SharedState::new());
+        fs::write(dir.join("app.log"), "one\n").unwrap();
+        fs::write(dir.join("app.log.1"), "old\n").unwrap();
+
+        let (sender, receiver) = mpsc::channel::<(String, String)>();
+        let watcher = thread::spawn({
+            let pattern = format!("{}/*.log*", dir.display());
+            let conn = Arc::clone(&conn);
+            let signal = Arc::clone(&signal);
+            move || {
+                let sink = move |path: &Path, line: String| {
+                    let name = path.file_name().unwrap().to_string_lossy().to_string();
+                    sender.send((name, line)).is_ok()
+                };
+                watch_glob(&pattern, sink, conn, signal)
+            }
+        });
+        let next = || receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(next(), ("app.log".to_string(), "one".to_string()));
+
+        fs::rename(dir.join("app.log.1"), dir.join("app.log.2")).unwrap();
+        fs::rename(dir.join("app.log"), dir.join("app.log.1")).unwrap();
+        fs::write(dir.join("app.log"), "two\n").unwrap();
+        assert_eq!(next(), ("app.log".to_string(), "two".to_string()));
+        // by the time a new file is picked up, the watcher has seen the rotated ones
+        thread::sleep(Duration::from_millis(GLOB_POLL_INTERVAL_MS));
+        fs::write(dir.join("other.log"), "three\n").unwrap();
+        assert_eq!(next(), ("other.log".to_string(), "three".to_string()));
+
+        signal.stop();
+        watcher.join().unwrap();
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), Vec::new());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncation_reads_the_file_from_the_start() {
+        let dir = std::env::temp_dir().join(format!("logparsely-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let path = dir.join("app.log");
+        fs::write(&path, "first line\nsecond line\n").unwrap();
+
+        let mut tailer = tailer(&path, &conn, &Arc::new(SharedState::new()));
+        assert_eq!(tailer.next().as_deref(), Some("first line"));
+        assert_eq!(tailer.next().as_deref(), Some("second line"));
+        // copytruncate empties the file in place, keeping its identity
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, "third\n");
+        assert_eq!(tailer.next().as_deref(), Some("third"));
+        assert_eq!(tailer.committed_offset().offset, "third\n".len() as u64);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides event timestamp extraction.
+///
+/// Services name their timestamp differently (`ts`, `time`, `@timestamp`) and write it in different formats (RFC 3339, epoch seconds or millis).
+/// This module defines a `TimestampRule` that finds the timestamp of a row and normalizes it to an RFC 3339 UTC timestamp.
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use rusqlite::types::Value as SqlValue;
+
+/// The fields looked at when a rule names none, compared case-insensitively and in this order.
+const DEFAULT_FIELDS: &[&str] = &[
+    "@timestamp",
+    "timestamp",
+    "ts",
+    "time",
+    "datetime",
+    "date",
+    "eventTime",
+    "timeUnixNano",
+    "observedTimeUnixNano",
+    "time_unix_nano",
+    "observed_time_unix_nano",
+    "_prefix_ts",
+];
+/// Epoch values below these are taken as seconds, millis and micros respectively, anything larger as nanos.
+/// Seconds up to the year 5138, millis from 1973, micros from 1973 and nanos from 1973 are told apart correctly.
+const MAX_EPOCH_SECONDS: f64 = 1e11;
+const MAX_EPOCH_MILLIS: f64 = 1e14;
+const MAX_EPOCH_MICROS: f64 = 1e17;
+/// Numbers read as an epoch before 2000-01-01 by `TimestampFormat::Auto` are taken for something else, such as a duration
+/// (`"time": 0.25`) or the unset `timeUnixNano` of an OTLP record.
+const MIN_AUTO_EPOCH_SECONDS: i64 = 946_684_800;
+/// Date-time layouts tried, in order, for text that is neither RFC 3339 nor a number. Those without an offset are taken as UTC.
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+];
+const OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%d/%b/%Y:%H:%M:%S %z",
+];
+
+/// How the value of a timestamp field is read.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// RFC 3339 and other common date-time layouts, or epoch seconds/millis/micros/nanos told apart by magnitude from the year 2000 on.
+    #[default]
+    Auto,
+    Rfc3339,
+    EpochSeconds,
+    EpochMillis,
+    EpochMicros,
+    EpochNanos,
+    /// A `strftime` layout such as `%d/%b/%Y:%H:%M:%S %z`, taken as UTC if it has no offset.
+    Layout(String),
+}
+
+impl TimestampFormat {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "auto" => Ok(TimestampFormat::Auto),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "epoch_s" => Ok(TimestampFormat::EpochSeconds),
+            "epoch_ms" => Ok(TimestampFormat::EpochMillis),
+            "epoch_us" => Ok(TimestampFormat::EpochMicros),
+            "epoch_ns" => Ok(TimestampFormat::EpochNanos),
+            layout if layout.contains('%') => Ok(TimestampFormat::Layout(layout.to_string())),
+            other => Err(format!(
+                "unknown timestamp format {other}, expected auto, rfc3339, epoch_s, epoch_ms, epoch_us, epoch_ns or a strftime layout"
+            )),
+        }
+    }
+
+    /// Reads `value`, `None` if it is not a timestamp in this format.
+    fn parse(&self, value: &SqlValue) -> Option<DateTime<Utc>> {
+        let number = match value {
+            SqlValue::Integer(i) => Some(*i as f64),
+            SqlValue::Real(f) => Some(*f),
+            SqlValue::Text(text) => text.trim().parse::<f64>().ok(),
+            SqlValue::Null | SqlValue::Blob(_) => None,
+        };
+        let text = match value {
+            SqlValue::Text(text) => Some(text.trim()),
+            _ => None,
+        };
+
+        match self {
+            TimestampFormat::Auto => match (number, text) {
+                (Some(number), _) => {
+                    let nanos_per_unit = if number.abs() < MAX_EPOCH_SECONDS {
+                        1e9
+                    } else if number.abs() < MAX_EPOCH_MILLIS {
+                        1e6
+                    } else if number.abs() < MAX_EPOCH_MICROS {
+                        1e3
+                    } else {
+                        1.0
+                    };
+                    from_epoch(number, nanos_per_unit)
+                        .filter(|ts| ts.timestamp() >= MIN_AUTO_EPOCH_SECONDS)
+                }
+                (None, Some(text)) => parse_text(text),
+                (None, None) => None,
+            },
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(text?)
+                .ok()
+                .map(|ts| ts.with_timezone(&Utc)),
+            TimestampFormat::EpochSeconds => from_epoch(number?, 1e9),
+            TimestampFormat::EpochMillis => from_epoch(number?, 1e6),
+            TimestampFormat::EpochMicros => from_epoch(number?, 1e3),
+            TimestampFormat::EpochNanos => from_epoch(number?, 1.0),
+            TimestampFormat::Layout(layout) => parse_layout(text?, layout),
+        }
+    }
+}
+
+/// Which fields of a row hold its event timestamp and how they are read.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TimestampRule {
+    /// The fields tried in order, the common timestamp field names if empty.
+    pub fields: Vec<String>,
+    pub format: TimestampFormat,
+}
+
+impl TimestampRule {
+    /// Creates a rule from its CLI spelling, `<field>[,<field>...][:<format>]` or just `auto`,
+    /// e.g. `ts:epoch_ms`, `@timestamp,time` or `time:%d/%b/%Y:%H:%M:%S %z`.
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        if spec == "auto" {
+            return Ok(TimestampRule::default());
+        }
+        let (fields, format) = match spec.split_once(':') {
+            Some((fields, format)) => (fields, TimestampFormat::from_name(format)?),
+            None => (spec, TimestampFormat::Auto),
+        };
+        let fields = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty() && *field != "auto")
+            .map(str::to_string)
+            .collect();
+        Ok(TimestampRule { fields, format })
+    }
+
+    /// Returns the event timestamp of `row` as RFC 3339 in UTC with microseconds, `None` if no field holds one.
+    pub fn extract(&self, row: &HashMap<String, SqlValue>) -> Option<String> {
+        let ts = if self.fields.is_empty() {
+            DEFAULT_FIELDS.iter().find_map(|name| {
+                row.iter()
+                    .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .find_map(|(_, value)| self.format.parse(value))
+            })
+        } else {
+            self.fields
+                .iter()
+                .find_map(|field| row.get(field).and_then(|value| self.format.parse(value)))
+        };
+        ts.map(|ts| format_timestamp(&ts))
+    }
+}
+
+/// Formats a timestamp the way every timestamp column is stored, e.g. `2024-01-02T03:04:05.123456Z`, which sorts in time order.
+pub fn format_timestamp(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn from_epoch(value: f64, nanos_per_unit: f64) -> Option<DateTime<Utc>> {
+    // rounded to the microseconds timestamps are stored with, so 1704164645.123 does not end up as .122999
+    let nanos = (value * nanos_per_unit / 1e3).round() * 1e3;
+    if !nanos.is_finite() || nanos.abs() >= i64::MAX as f64 {
+        return None;
+    }
+    Some(Utc.timestamp_nanos(nanos as i64))
+}
+
+fn parse_text(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(text) {
+        return Some(ts.with_timezone(&Utc));
+    }
+    if let Ok(ts) = DateTime::parse_from_rfc2822(text) {
+        return Some(ts.with_timezone(&Utc));
+    }
+    // ISO 8601 allows a comma before the fraction, as Python's logging writes it
+    let text = text.replacen(',', ".", 1);
+    OFFSET_FORMATS
+        .iter()
+        .chain(NAIVE_FORMATS)
+        .find_map(|layout| parse_layout(&text, layout))
+}
+
+fn parse_layout(text: &str, layout: &str) -> Option<DateTime<Utc>> {
+    if let Ok(ts) = DateTime::parse_from_str(text, layout) {
+        return Some(ts.with_timezone(&Utc));
+    }
+    if let Ok(ts) = NaiveDateTime::parse_from_str(text, layout) {
+        return Some(ts.and_utc());
+    }
+    NaiveDate::parse_from_str(text, layout)
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|ts| ts.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(spec: &str, fields: &[(&str, SqlValue)]) -> Option<String> {
+        let row: HashMap<String, SqlValue> = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        TimestampRule::from_spec(spec).unwrap().extract(&row)
+    }
+
+    #[test]
+    fn test_auto_detects_fields_and_formats() {
+        let text = |s: &str| SqlValue::Text(s.to_string());
+        let expected = Some("2024-01-02T03:04:05.123000Z".to_string());
+
+        assert_eq!(
+            extract(
+                "auto",
+                &[("@timestamp", text("2024-01-02T04:04:05.123+01:00"))]
+            ),
+            expected
+        );
+        assert_eq!(
+            extract(
+                "auto",
+                &[("Timestamp", SqlValue::Integer(1_704_164_645_123))]
+            ),
+            expected
+        );
+        assert_eq!(
+            extract("auto", &[("ts", SqlValue::Real(1_704_164_645.123))]),
+            expected
+        );
+        assert_eq!(
+            extract("auto", &[("time", text("1704164645123000000"))]),
+            expected
+        );
+        assert_eq!(
+            extract("auto", &[("time", text("2024-01-02 03:04:05,123"))]),
+            expected
+        );
+        assert_eq!(
+            extract("auto", &[("msg", text("2024-01-02T03:04:05Z"))]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_auto_skips_numbers_too_small_to_be_epochs() {
+        assert_eq!(extract("auto", &[("time", SqlValue::Real(0.25))]), None);
+        assert_eq!(
+            extract(
+                "auto",
+                &[
+                    ("timeUnixNano", SqlValue::Text("0".to_string())),
+                    (
+                        "observedTimeUnixNano",
+                        SqlValue::Text("1704164645123000000".to_string())
+                    )
+                ]
+            ),
+            Some("2024-01-02T03:04:05.123000Z".to_string())
+        );
+        // a format named explicitly takes the number as it is
+        assert_eq!(
+            extract("time:epoch_s", &[("time", SqlValue::Real(0.25))]),
+            Some("1970-01-01T00:00:00.250000Z".to_string())
+        );
+    }
+
+    #[test]
+    fn test_configured_fields_and_formats() {
+        let text = |s: &str| SqlValue::Text(s.to_string());
+        assert_eq!(
+            extract(
+                "when:%d/%b/%Y:%H:%M:%S %z",
+                &[
+                    ("when", text("02/Jan/2024:03:04:05 -0700")),
+                    ("ts", text("x"))
+                ]
+            ),
+            Some("2024-01-02T10:04:05.000000Z".to_string())
+        );
+        assert_eq!(
+            extract(
+                "created,ts:epoch_s",
+                &[("ts", SqlValue::Integer(1_704_164_645))]
+            ),
+            Some("2024-01-02T03:04:05.000000Z".to_string())
+        );
+        assert_eq!(extract("ts:rfc3339", &[("ts", SqlValue::Integer(1))]), None);
+        assert!(TimestampRule::from_spec("ts:weird").is_err());
+    }
+}
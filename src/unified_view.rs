@@ -0,0 +1,364 @@
+// Copyright (c) Microsoft Corporation.
+
+/// Provides the unified view over all sources.
+///
+/// Every source is ingested into a table of its own, with the columns its records happen to have.
+/// This module maintains the `all_logs` view that unions those tables over the superset of their columns,
+/// so the logs of every source can be queried (and ordered by `_ts`) at once.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::ingestion::SOURCE_ID_COL;
+use crate::storage::quote_identifier;
+
+/// The view unioning every source table.
+pub const ALL_LOGS_VIEW: &str = "all_logs";
+/// Column of the view naming the table a row comes from.
+pub const SOURCE_COL: &str = "_source";
+/// SQLite's default `SQLITE_MAX_COMPOUND_SELECT`, the most tables a single `UNION ALL` can span.
+const MAX_VIEW_TABLES: usize = 500;
+/// SQLite's default `SQLITE_MAX_COLUMN`, a view with more columns is created but cannot be queried.
+const MAX_VIEW_COLUMNS: usize = 2000;
+
+/// Represents errors that can occur when refreshing the `all_logs` view.
+///
+/// # Variants
+///
+/// * `SqlError` - Represents an error that occurred while executing an SQL command, the previous view is kept.
+#[derive(Debug)]
+pub enum ViewRefreshError {
+    SqlError(rusqlite::Error),
+}
+
+impl fmt::Display for ViewRefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewRefreshError::SqlError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ViewRefreshError {
+    fn from(e: rusqlite::Error) -> Self {
+        ViewRefreshError::SqlError(e)
+    }
+}
+
+/// The columns of every source table, kept up to date by the tables themselves so refreshing the view does not read the schema of every table.
+#[derive(Debug, Default)]
+pub struct ViewCatalog {
+    /// The columns of every source table in the view by table name, loaded from the database the first time the view is refreshed.
+    tables: Option<BTreeMap<String, Vec<String>>>,
+    /// The tables, and the columns of each table, left out of the view past SQLite's limits, so each is reported once.
+    left_out_tables: HashSet<String>,
+    left_out_columns: HashSet<(String, String)>,
+}
+
+impl ViewCatalog {
+    /// Records the columns of `table_name` and recreates the `all_logs` view.
+    ///
+    /// Source tables are those whose rows carry a `_source_id`, which leaves out the metadata, child and overflow tables.
+    /// Columns are matched case-insensitively, the way SQLite compares them, and a table lacking a column contributes `NULL`s.
+    /// Tables and columns past SQLite's limits are left out of the view and reported once, see `include`.
+    ///
+    /// The method takes three arguments:
+    /// * `conn`: the connection to the SQLite database, already locked by the caller.
+    /// * `table_name`: the table whose columns changed, or that was just opened.
+    /// * `columns`: the columns the table has now.
+    ///
+    /// The method returns a `Result<(), ViewRefreshError>`. The view is left as it was if it cannot be recreated.
+    pub fn refresh(
+        &mut self,
+        conn: &Connection,
+        table_name: &str,
+        columns: Vec<String>,
+    ) -> Result<(), ViewRefreshError> {
+        if self.tables.is_none() {
+            let loaded = load_source_tables(conn)?;
+            self.tables = Some(BTreeMap::new());
+            for (name, columns) in loaded {
+                self.include(&name, columns);
+            }
+        }
+        if columns.iter().any(|col| col == SOURCE_ID_COL) {
+            self.include(table_name, columns);
+        }
+        recreate_view(conn, self.tables.as_ref().expect("tables were just loaded"))
+    }
+
+    /// Records the columns of `table_name` that fit in the view.
+    ///
+    /// Columns already in the view are kept, so the view only ever grows. A table past `MAX_VIEW_TABLES`, or new columns past `MAX_VIEW_COLUMNS`,
+    /// are left out of the view rather than failing every later refresh, and are reported the first time they are.
+    fn include(&mut self, table_name: &str, columns: Vec<String>) {
+        let tables = self.tables.get_or_insert_with(BTreeMap::new);
+        if !tables.contains_key(table_name) && tables.len() >= MAX_VIEW_TABLES {
+            if self.left_out_tables.insert(table_name.to_string()) {
+                eprintln!(
+                    "Left the {table_name} table out of the {ALL_LOGS_VIEW} view, it can union at most {MAX_VIEW_TABLES} tables"
+                );
+            }
+            return;
+        }
+
+        let mut included = tables.remove(table_name).unwrap_or_default();
+        let mut folded: HashSet<String> = tables
+            .values()
+            .chain([&included])
+            .flatten()
+            .map(|col| col.to_ascii_lowercase())
+            .collect();
+        let mut newly_left_out = 0;
+        for col in columns {
+            let folded_col = col.to_ascii_lowercase();
+            if included
+                .iter()
+                .any(|own| own.to_ascii_lowercase() == folded_col)
+            {
+                continue;
+            }
+            // plus the `_source` column
+            if folded.contains(&folded_col) || folded.len() + 1 < MAX_VIEW_COLUMNS {
+                folded.insert(folded_col);
+                included.push(col);
+            } else if self.left_out_columns.insert((table_name.to_string(), col)) {
+                newly_left_out += 1;
+            }
+        }
+        if newly_left_out > 0 {
+            eprintln!(
+                "Left {newly_left_out} columns of the {table_name} table out of the {ALL_LOGS_VIEW} view, it can hold at most {MAX_VIEW_COLUMNS} columns"
+            );
+        }
+        tables.insert(table_name.to_string(), included);
+    }
+}
+
+/// Reads the columns of every source table in the database.
+fn load_source_tables(conn: &Connection) -> Result<BTreeMap<String, Vec<String>>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )?;
+    let table_names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    let mut tables = BTreeMap::new();
+    for table_name in table_names {
+        let mut stmt = conn.prepare(&format!(
+            "PRAGMA table_info({})",
+            quote_identifier(&table_name)
+        ))?;
+        let columns = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if columns.iter().any(|col| col == SOURCE_ID_COL) {
+            tables.insert(table_name, columns);
+        }
+    }
+    Ok(tables)
+}
+
+fn recreate_view(
+    conn: &Connection,
+    tables: &BTreeMap<String, Vec<String>>,
+) -> Result<(), ViewRefreshError> {
+    // the first spelling of a column wins, SQLite would reject `Level` and `level` side by side
+    let mut folded: HashSet<String> = HashSet::new();
+    let mut all_columns: Vec<String> = Vec::new();
+    for columns in tables.values() {
+        for col in columns {
+            if folded.insert(col.to_ascii_lowercase()) {
+                all_columns.push(col.clone());
+            }
+        }
+    }
+    all_columns.sort();
+
+    let selects: Vec<String> = tables
+        .iter()
+        .map(|(table_name, columns)| {
+            let mut fields = vec![format!(
+                "{} AS {}",
+                quote_literal(table_name),
+                quote_identifier(SOURCE_COL)
+            )];
+            for col in &all_columns {
+                match columns.iter().find(|own| own.eq_ignore_ascii_case(col)) {
+                    Some(own) => fields.push(format!(
+                        "{} AS {}",
+                        quote_identifier(own),
+                        quote_identifier(col)
+                    )),
+                    None => fields.push(format!("NULL AS {}", quote_identifier(col))),
+                }
+            }
+            format!(
+                "SELECT {} FROM {}",
+                fields.join(", "),
+                quote_identifier(table_name)
+            )
+        })
+        .collect();
+
+    // dropping the transaction without committing rolls it back, so a failed refresh leaves the connection usable
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute_batch(&format!("DROP VIEW IF EXISTS {ALL_LOGS_VIEW};"))?;
+    if !selects.is_empty() {
+        transaction.execute_batch(&format!(
+            "CREATE VIEW {ALL_LOGS_VIEW} AS {};",
+            selects.join(" UNION ALL ")
+        ))?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table_name: &str) -> Vec<String> {
+        conn.prepare(&format!("PRAGMA table_info({table_name})"))
+            .unwrap()
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_view_unions_source_tables_over_all_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE api (_source_id TEXT, _ts TEXT, Level TEXT, status INTEGER);
+             CREATE TABLE worker (_source_id TEXT, _ts TEXT, level TEXT, job TEXT);
+             CREATE TABLE api__tags (_parent_id INTEGER, value TEXT);
+             CREATE TABLE _column_mappings (table_name TEXT, key TEXT, column_name TEXT);
+             INSERT INTO api VALUES ('a', '2024-01-02T00:00:02.000000Z', 'info', 200);
+             INSERT INTO worker VALUES ('w', '2024-01-02T00:00:01.000000Z', 'warn', 'sync');",
+        )
+        .unwrap();
+        let mut catalog = ViewCatalog::default();
+        catalog
+            .refresh(&conn, "api", columns(&conn, "api"))
+            .unwrap();
+
+        let rows: Vec<(String, String, Option<i64>, Option<String>)> = conn
+            .prepare("SELECT _source, Level, status, job FROM all_logs ORDER BY _ts")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "worker".to_string(),
+                    "warn".to_string(),
+                    None,
+                    Some("sync".to_string())
+                ),
+                ("api".to_string(), "info".to_string(), Some(200), None),
+            ]
+        );
+
+        conn.execute("ALTER TABLE worker ADD attempt INTEGER", ())
+            .unwrap();
+        catalog
+            .refresh(&conn, "worker", columns(&conn, "worker"))
+            .unwrap();
+        let attempts: i64 = conn
+            .query_row("SELECT COUNT(attempt) FROM all_logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(attempts, 0);
+    }
+
+    #[test]
+    fn test_columns_past_sqlite_limits_are_left_out_and_later_sources_still_appear() {
+        let conn = Connection::open_in_memory().unwrap();
+        let wide: Vec<String> = (0..MAX_VIEW_COLUMNS - 2)
+            .map(|i| format!("c{i} TEXT"))
+            .collect();
+        conn.execute_batch(&format!(
+            "CREATE TABLE api (_source_id TEXT, msg TEXT);
+             CREATE TABLE wide (_source_id TEXT, {});",
+            wide.join(", ")
+        ))
+        .unwrap();
+        let mut catalog = ViewCatalog::default();
+        catalog
+            .refresh(&conn, "wide", columns(&conn, "wide"))
+            .unwrap();
+
+        // `late` does not fit, the rest of the table does
+        conn.execute_batch(
+            "CREATE TABLE later (_source_id TEXT, msg TEXT, late TEXT);
+             INSERT INTO later VALUES ('l', 'hello', 'dropped');",
+        )
+        .unwrap();
+        catalog
+            .refresh(&conn, "later", columns(&conn, "later"))
+            .unwrap();
+        conn.execute("ALTER TABLE api ADD extra TEXT", ()).unwrap();
+        catalog
+            .refresh(&conn, "api", columns(&conn, "api"))
+            .unwrap();
+
+        let (source, msg): (String, String) = conn
+            .query_row("SELECT _source, msg FROM all_logs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((source.as_str(), msg.as_str()), ("later", "hello"));
+        assert_eq!(columns(&conn, "all_logs").len(), MAX_VIEW_COLUMNS);
+        assert!(!columns(&conn, "all_logs").contains(&"late".to_string()));
+        assert!(conn.is_autocommit());
+    }
+
+    #[test]
+    fn test_tables_past_sqlite_limits_are_left_out_and_the_view_keeps_up() {
+        let conn = Connection::open_in_memory().unwrap();
+        for i in 0..=MAX_VIEW_TABLES {
+            conn.execute_batch(&format!(
+                "CREATE TABLE t{i:03} (_source_id TEXT, msg TEXT);
+                 INSERT INTO t{i:03} VALUES ('{i}', 'hello');"
+            ))
+            .unwrap();
+        }
+        let mut catalog = ViewCatalog::default();
+        catalog
+            .refresh(&conn, "t000", columns(&conn, "t000"))
+            .unwrap();
+        let sources: i64 = conn
+            .query_row("SELECT COUNT(DISTINCT _source) FROM all_logs", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(sources, MAX_VIEW_TABLES as i64);
+
+        // the tables in the view still pick up new columns
+        conn.execute("ALTER TABLE t000 ADD level TEXT", ()).unwrap();
+        let last = format!("t{MAX_VIEW_TABLES:03}");
+        catalog
+            .refresh(&conn, &last, columns(&conn, &last))
+            .unwrap();
+        catalog
+            .refresh(&conn, "t000", columns(&conn, "t000"))
+            .unwrap();
+        let levels: i64 = conn
+            .query_row("SELECT COUNT(level) FROM all_logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(levels, 0);
+    }
+}